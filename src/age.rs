@@ -0,0 +1,99 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{diff::Diffs, memory_map::MemoryMap};
+
+/// When each map was first seen over a watch session, keyed by its identity.
+#[derive(Default)]
+pub struct Ages {
+    first_seen: HashMap<u64, Instant>,
+}
+
+impl Ages {
+    /// Start the clock for the added maps and forget the removed ones.
+    pub fn record(&mut self, diffs: &Diffs, now: Instant) {
+        for m in &diffs.added {
+            self.first_seen.entry(m.identity()).or_insert(now);
+        }
+
+        for m in &diffs.removed {
+            self.first_seen.remove(&m.identity());
+        }
+    }
+
+    /// How long the map has been around, or `None` if it's new.
+    pub fn age(&self, m: &MemoryMap, now: Instant) -> Option<Duration> {
+        self.first_seen
+            .get(&m.identity())
+            .map(|first_seen| now - *first_seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(from: usize) -> MemoryMap {
+        format!(
+            "{:x}-{:x} rw-p 00000000 00:00 0 \nRss: 4 kB\nVmFlags: rd wr\n",
+            from,
+            from + 0x1000
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ages() {
+        let start = Instant::now();
+        let later = |secs| start + Duration::from_secs(secs);
+        let mut ages = Ages::default();
+
+        ages.record(
+            &Diffs {
+                added: vec![map(0x1000)],
+                ..Default::default()
+            },
+            start,
+        );
+        ages.record(
+            &Diffs {
+                added: vec![map(0x3000)],
+                ..Default::default()
+            },
+            later(5),
+        );
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            ages.age(&map(0x1000), later(10))
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            ages.age(&map(0x3000), later(10))
+        );
+        assert_eq!(None, ages.age(&map(0x5000), later(10)));
+
+        ages.record(
+            &Diffs {
+                removed: vec![map(0x1000)],
+                ..Default::default()
+            },
+            later(15),
+        );
+        assert_eq!(None, ages.age(&map(0x1000), later(20)));
+
+        ages.record(
+            &Diffs {
+                added: vec![map(0x1000)],
+                ..Default::default()
+            },
+            later(25),
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            ages.age(&map(0x1000), later(30))
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Rewriting paths so captures can be shared without giving away usernames or layout.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::process::ProcessInfo;
+
+/// How paths are rewritten for `--anonymize-paths`. The placeholders are derived from
+/// the path alone, so a path is rewritten the same way every time.
+pub struct Anonymizer<'a> {
+    /// Prefixes to replace beyond home directories, each becomes `<prefixN>` where N is
+    /// its position in the list.
+    pub prefixes: &'a [String],
+    pub hash_basenames: bool,
+}
+
+fn short_hash(value: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+impl Anonymizer<'_> {
+    /// Rewrite a path. Pseudo paths such as `[heap]` are left alone.
+    pub fn path(&self, path: &str) -> String {
+        if !path.starts_with('/') {
+            return path.to_string();
+        }
+
+        let mut path = match self
+            .prefixes
+            .iter()
+            .enumerate()
+            .find(|(_, prefix)| path.starts_with(prefix.as_str()))
+        {
+            Some((index, prefix)) => format!("<prefix{}>{}", index + 1, &path[prefix.len()..]),
+            None => path.to_string(),
+        };
+
+        if let Some(rest) = path.strip_prefix("/home/") {
+            let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            path = format!("/home/user-{}{}", short_hash(user), rest);
+        }
+
+        if self.hash_basenames {
+            if let Some((dir, basename)) = path.rsplit_once('/') {
+                // Keep the extension, eg. `.so.6`, as it says what kind of file it is.
+                let (name, extension) =
+                    basename.split_at(basename.find('.').unwrap_or(basename.len()));
+                if !name.is_empty() {
+                    path = format!("{}/{}{}", dir, short_hash(name), extension);
+                }
+            }
+        }
+
+        path
+    }
+
+    /// Rewrite the paths in the details of a process, its executable and any argument
+    /// that's a path, as they're written out next to the maps.
+    pub fn process(&self, info: ProcessInfo) -> ProcessInfo {
+        ProcessInfo {
+            cmdline: info.cmdline.iter().map(|arg| self.path(arg)).collect(),
+            exe: info.exe.map(|exe| self.path(&exe.to_string_lossy()).into()),
+            ..info
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_path() {
+        let prefixes = vec!["/opt/acme".to_string()];
+        let anonymizer = Anonymizer {
+            prefixes: &prefixes,
+            hash_basenames: false,
+        };
+
+        assert_eq!("[heap]", anonymizer.path("[heap]"));
+        assert_eq!("/usr/lib/libc.so.6", anonymizer.path("/usr/lib/libc.so.6"));
+        assert_eq!(
+            "<prefix1>/bin/server",
+            anonymizer.path("/opt/acme/bin/server")
+        );
+
+        let home = anonymizer.path("/home/stephenwakely/.cargo/bin/shmaps");
+        assert!(!home.contains("stephenwakely"));
+        assert!(home.ends_with("/.cargo/bin/shmaps"));
+        assert_eq!(
+            home,
+            anonymizer.path("/home/stephenwakely/.cargo/bin/shmaps")
+        );
+    }
+
+    #[test]
+    fn test_anonymize_process() {
+        let anonymizer = Anonymizer {
+            prefixes: &[],
+            hash_basenames: false,
+        };
+        let info = anonymizer.process(ProcessInfo {
+            pid: 42,
+            comm: "server".to_string(),
+            cmdline: [
+                "/home/alice/bin/server",
+                "--config",
+                "/home/alice/server.toml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            exe: Some("/home/alice/bin/server".into()),
+        });
+
+        assert!(!info.to_string().contains("alice"));
+        assert_eq!("--config", info.cmdline[1]);
+        assert_eq!(info.cmdline[0], info.exe.unwrap().to_string_lossy());
+    }
+
+    #[test]
+    fn test_hash_basenames() {
+        let anonymizer = Anonymizer {
+            prefixes: &[],
+            hash_basenames: true,
+        };
+
+        let path = anonymizer.path("/usr/lib/libsecret.so.6");
+        assert!(path.starts_with("/usr/lib/"));
+        assert!(path.ends_with(".so.6"));
+        assert!(!path.contains("libsecret"));
+    }
+}
@@ -0,0 +1,185 @@
+//! `--container`, finding the cgroup of a container from its ID or name without talking
+//! to the container runtime.
+//!
+//! Docker, Podman and containerd all put each container in a cgroup named after its ID,
+//! such as `system.slice/docker-<id>.scope` or `docker/<id>`, so a container is found by
+//! looking for that under `/sys/fs/cgroup`. Names are looked up in Docker's own records
+//! of its containers.
+//!
+//! The kernel writes the path of each map relative to the root of whoever reads smaps,
+//! not the root of the process, so a container's files can come out under wherever its
+//! root is from here, eg. `/var/lib/docker/overlay2/<id>/merged/usr/lib/libc.so.6`. They
+//! are put back the way the container sees them through `/proc/<pid>/root`.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::process;
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+pub const DOCKER_CONTAINERS: &str = "/var/lib/docker/containers";
+
+/// How deep under the cgroup root to look, which is plenty for the runtimes' layouts
+/// such as `kubepods.slice/kubepods-burstable.slice/kubepods-....slice/cri-containerd-<id>.scope`.
+const MAX_DEPTH: usize = 6;
+
+const PREFIXES: &[&str] = &["docker-", "libpod-", "cri-containerd-", "crio-"];
+
+/// The container ID a cgroup is named after, if it is a container's.
+fn container_id(cgroup_name: &str) -> Option<&str> {
+    let id = PREFIXES
+        .iter()
+        .find_map(|prefix| cgroup_name.strip_prefix(prefix))
+        .unwrap_or(cgroup_name);
+    let id = id.strip_suffix(".scope").unwrap_or(id);
+    (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(id)
+}
+
+/// Every container cgroup under `dir`, keyed by container ID. With cgroup v1 a container
+/// has a cgroup under each controller, so an ID can have several. Anything that can't be
+/// read is skipped.
+fn container_cgroups(dir: &Path, depth: usize, found: &mut BTreeMap<String, Vec<PathBuf>>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_name().to_str().and_then(container_id) {
+            Some(id) => found.entry(id.to_string()).or_default().push(path),
+            None if depth < MAX_DEPTH => container_cgroups(&path, depth + 1, found),
+            None => {}
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct DockerConfig {
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+/// The ID of the Docker container with this name, if there is one.
+#[cfg(feature = "serde")]
+fn docker_id(containers: &Path, name: &str) -> Option<String> {
+    fs::read_dir(containers)
+        .ok()?
+        .filter_map(|entry| {
+            let config = fs::read(entry.ok()?.path().join("config.v2.json")).ok()?;
+            serde_json::from_slice::<DockerConfig>(&config).ok()
+        })
+        .find(|config| config.name.trim_start_matches('/') == name)
+        .map(|config| config.id)
+}
+
+/// Docker's records are JSON, so without serde containers can only be found by ID.
+#[cfg(not(feature = "serde"))]
+fn docker_id(_containers: &Path, _name: &str) -> Option<String> {
+    None
+}
+
+/// The cgroups of the containers whose ID starts with `container`, or failing that of
+/// the Docker container named `container`, keyed by their full ID.
+pub fn find(
+    cgroup_root: &Path,
+    containers: &Path,
+    container: &str,
+) -> io::Result<BTreeMap<String, Vec<PathBuf>>> {
+    // Only the root has to be there, so a missing cgroup filesystem is an error.
+    fs::read_dir(cgroup_root)?;
+    let mut cgroups = BTreeMap::new();
+    container_cgroups(cgroup_root, 0, &mut cgroups);
+
+    let mut matches = cgroups.clone();
+    matches.retain(|id, _| id.starts_with(container));
+    if matches.is_empty() {
+        if let Some(id) = docker_id(containers, container) {
+            matches = cgroups;
+            matches.retain(|other, _| *other == id);
+        }
+    }
+    Ok(matches)
+}
+
+/// Where the root of the process is from here, read from `/proc/<pid>/root`, if it can be
+/// read and isn't the same as ours.
+pub fn root(proc_root: &Path, pid: usize) -> Option<PathBuf> {
+    fs::read_link(process::proc_path(proc_root, pid, "root"))
+        .ok()
+        .filter(|root| root != Path::new("/"))
+}
+
+/// The path as a process whose root is at `root` sees it, if it's under there.
+pub fn path_inside(root: &Path, path: &str) -> Option<String> {
+    let inside = Path::new(path).strip_prefix(root).ok()?;
+    Some(Path::new("/").join(inside).to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    const ID: &str = "3f4e2a1b9c8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f";
+
+    #[test]
+    fn test_container_id() {
+        assert_eq!(Some(ID), container_id(&format!("docker-{}.scope", ID)));
+        assert_eq!(Some(ID), container_id(ID));
+        assert_eq!(None, container_id("system.slice"));
+        assert_eq!(None, container_id("docker-1234.scope"));
+    }
+
+    #[test]
+    fn test_path_inside() {
+        let root = Path::new("/var/lib/docker/overlay2/abc/merged");
+        assert_eq!(
+            Some("/usr/lib/libc.so.6".to_string()),
+            path_inside(
+                root,
+                "/var/lib/docker/overlay2/abc/merged/usr/lib/libc.so.6"
+            )
+        );
+        assert_eq!(None, path_inside(root, "/usr/lib/libc.so.6"));
+        assert_eq!(None, path_inside(root, "[heap]"));
+    }
+
+    #[test]
+    fn test_find() {
+        let root = Scratch::new("container");
+        let cgroup = root
+            .join("cgroup/system.slice")
+            .join(format!("docker-{}.scope", ID));
+        fs::create_dir_all(&cgroup).unwrap();
+        fs::create_dir_all(root.join("cgroup/system.slice/cron.service")).unwrap();
+        let config = root.join("containers").join(ID);
+        fs::create_dir_all(&config).unwrap();
+        fs::write(
+            config.join("config.v2.json"),
+            format!(r#"{{"ID":"{}","Name":"/web","State":{{}}}}"#, ID),
+        )
+        .unwrap();
+
+        let expected = BTreeMap::from([(ID.to_string(), vec![cgroup])]);
+        let find =
+            |container| find(&root.join("cgroup"), &root.join("containers"), container).unwrap();
+        let (by_id, by_name, missing) = (find("3f4e"), find("web"), find("db"));
+
+        assert_eq!(expected, by_id);
+        if cfg!(feature = "serde") {
+            assert_eq!(expected, by_name);
+        }
+        assert!(missing.is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! `--format csv`, one row per map. The columns are fixed rather than following
+//! `SIZE_FIELDS`, so scripts reading them don't break when a kernel adds a field.
+
+use std::io::{self, Write};
+
+use crate::{memory_map::MemoryMap, template::Fields, units::Units};
+
+const COLUMNS: &[&str] = &["start", "end", "perms", "offset", "device", "inode", "path"];
+
+/// The sizes after the fixed columns, in kernel order, as the column name and the
+/// kernel field.
+pub const SIZE_COLUMNS: &[(&str, &str)] = &[
+    ("size", "Size"),
+    ("rss", "Rss"),
+    ("pss", "Pss"),
+    ("pss_dirty", "Pss_Dirty"),
+    ("shared_clean", "Shared_Clean"),
+    ("shared_dirty", "Shared_Dirty"),
+    ("private_clean", "Private_Clean"),
+    ("private_dirty", "Private_Dirty"),
+    ("referenced", "Referenced"),
+    ("anonymous", "Anonymous"),
+    ("swap", "Swap"),
+    ("swap_pss", "SwapPss"),
+    ("locked", "Locked"),
+];
+
+/// Quote the field if it has anything in it that would otherwise break up the row.
+fn escape(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn write(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    units: Units,
+    delimiter: char,
+    header: bool,
+) -> io::Result<()> {
+    let separator = delimiter.to_string();
+
+    if header {
+        let suffix = units.suffix().to_lowercase();
+        let sizes = SIZE_COLUMNS
+            .iter()
+            .map(|(column, _)| format!("{}_{}", column, suffix));
+        let columns = COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .chain(sizes)
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}", columns.join(&separator))?;
+    }
+
+    for m in memory_map {
+        // A missing size is an empty field rather than 0, so it can be told apart.
+        let sizes = SIZE_COLUMNS.iter().map(|(_, field)| {
+            m.sizes
+                .get(field)
+                .map(|size| units.value(size.value()).to_string())
+                .unwrap_or_default()
+        });
+        let path = m.path.as_deref().unwrap_or_default();
+        let row = [
+            format!("{:x}", m.address_range.from),
+            format!("{:x}", m.address_range.to),
+            m.permissions.to_string(),
+            format!("{:x}", m.offset),
+            m.device.to_string(),
+            m.inode.to_string(),
+            escape(path, delimiter),
+        ]
+        .into_iter()
+        .chain(sizes)
+        .collect::<Vec<_>>();
+        writeln!(writer, "{}", row.join(&separator))?;
+    }
+
+    Ok(())
+}
+
+/// Like `write` but with just the `--fields` columns, headed by the names they were given.
+pub fn write_fields(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    fields: &Fields,
+    units: Units,
+    delimiter: char,
+    header: bool,
+) -> io::Result<()> {
+    let separator = delimiter.to_string();
+    if header {
+        let names = fields.names().map(|name| escape(name, delimiter));
+        writeln!(writer, "{}", names.collect::<Vec<_>>().join(&separator))?;
+    }
+    for m in memory_map {
+        let row = fields
+            .values(m, units)
+            .iter()
+            .map(|value| escape(&value.to_string(), delimiter))
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}", row.join(&separator))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let memory_map = [
+            "7f3d2c21b000-7f3d2c21e000 r-xp 00002000 fe:00 1234 /opt/a,b \"c\".so
+Size:                 12 kB
+Rss:                   8 kB
+VmFlags: rd ex
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+            "7f3d2c21e000-7f3d2c21f000 rw-p 00000000 00:00 0
+Rss:                   4 kB
+VmFlags: rd wr
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+        ];
+
+        let mut out = Vec::new();
+        write(&mut out, &memory_map, Units::Kb, ',', true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            "start,end,perms,offset,device,inode,path,size_kb,rss_kb,pss_kb,pss_dirty_kb,\
+             shared_clean_kb,shared_dirty_kb,private_clean_kb,private_dirty_kb,referenced_kb,\
+             anonymous_kb,swap_kb,swap_pss_kb,locked_kb",
+            lines[0]
+        );
+        assert_eq!(
+            "7f3d2c21b000,7f3d2c21e000,r-xp,2000,fe:00,1234,\"/opt/a,b \"\"c\"\".so\",12,8,,,,,,,,,,,",
+            lines[1]
+        );
+        assert_eq!(
+            "7f3d2c21e000,7f3d2c21f000,rw-p,0,00:00,0,,,4,,,,,,,,,,,",
+            lines[2]
+        );
+
+        let mut out = Vec::new();
+        write(&mut out, &memory_map[1..], Units::Bytes, '\t', false).unwrap();
+        assert_eq!(
+            "7f3d2c21e000\t7f3d2c21f000\trw-p\t0\t00:00\t0\t\t\t4096\t\t\t\t\t\t\t\t\t\t\t\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_fields() {
+        let m = "7f3d2c21b000-7f3d2c21e000 r-xp 00002000 fe:00 1234 /opt/a,b.so
+Rss:                   8 kB
+VmFlags: rd ex
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+
+        let fields = "rss,path".parse::<Fields>().unwrap();
+        let mut out = Vec::new();
+        write_fields(&mut out, &[m], &fields, Units::Kb, ',', true).unwrap();
+        assert_eq!(
+            "rss,path\n8,\"/opt/a,b.so\"\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
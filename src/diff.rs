@@ -1,13 +1,260 @@
-use crate::memory_map::MemoryMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    str::FromStr,
+};
 
-#[derive(Default)]
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    memory_map::{self, MemoryMap},
+    units::Units,
+};
+
+/// What changed between two readings, with the old and new map for each change.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Diffs {
     pub added: Vec<MemoryMap>,
     pub removed: Vec<MemoryMap>,
     pub changed: Vec<(MemoryMap, MemoryMap)>,
 }
 
-pub fn diff_sorted(vec1: &[MemoryMap], vec2: &[MemoryMap]) -> Diffs {
+/// A single entry of a diff, tagged with the kind of change for machine readable output.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum DiffEvent<'a> {
+    Added {
+        map: &'a MemoryMap,
+    },
+    Removed {
+        map: &'a MemoryMap,
+    },
+    Changed {
+        old: &'a MemoryMap,
+        new: &'a MemoryMap,
+    },
+    /// Comes after the changes, even when there weren't any.
+    Summary {
+        #[serde(flatten)]
+        summary: DiffSummary,
+    },
+}
+
+/// How many maps fall in one category of a diff, and the net change in kB they make.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DiffCount {
+    pub maps: usize,
+    pub rss: i64,
+    pub pss: i64,
+}
+
+impl DiffCount {
+    fn add(&mut self, rss: i64, pss: i64) {
+        self.maps += 1;
+        self.rss += rss;
+        self.pss += pss;
+    }
+}
+
+/// The headline numbers of a diff.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DiffSummary {
+    pub added: DiffCount,
+    pub removed: DiffCount,
+    pub changed: DiffCount,
+}
+
+impl DiffSummary {
+    pub fn format(&self, units: Units) -> String {
+        format!(
+            "ADDED {} maps ({} RSS, {} PSS), REMOVED {} ({} RSS, {} PSS), CHANGED {} (net {} RSS, {} PSS)",
+            self.added.maps,
+            units.format_delta(self.added.rss),
+            units.format_delta(self.added.pss),
+            self.removed.maps,
+            units.format_delta(self.removed.rss),
+            units.format_delta(self.removed.pss),
+            self.changed.maps,
+            units.format_delta(self.changed.rss),
+            units.format_delta(self.changed.pss),
+        )
+    }
+}
+
+fn rss_pss(m: &MemoryMap) -> (i64, i64) {
+    (
+        m.rss().unwrap_or_default() as i64,
+        m.pss().unwrap_or_default() as i64,
+    )
+}
+
+/// The change in (Rss, Pss) in kB going from `old` to `new`.
+pub fn delta(old: &MemoryMap, new: &MemoryMap) -> (i64, i64) {
+    let (old_rss, old_pss) = rss_pss(old);
+    let (new_rss, new_pss) = rss_pss(new);
+    (new_rss - old_rss, new_pss - old_pss)
+}
+
+/// The changes to the protection and flags of a map, such as `perms: rw-p -> r-xp`.
+pub fn transitions(old: &MemoryMap, new: &MemoryMap) -> Vec<String> {
+    let mut transitions = Vec::new();
+    if old.permissions != new.permissions {
+        transitions.push(format!("perms: {} -> {}", old.permissions, new.permissions));
+    }
+    if old.vm_flags != new.vm_flags {
+        transitions.push(format!("flags: {} -> {}", old.vm_flags, new.vm_flags));
+    }
+    transitions
+}
+
+/// The size fields that differ between the maps, such as
+/// `Rss: 1028 kB -> 2056 kB (+1028 kB)`. A field only one of them has counts as 0 in the
+/// other.
+pub fn field_changes(old: &MemoryMap, new: &MemoryMap, units: Units) -> Vec<String> {
+    let mut names = Vec::new();
+    for (name, _) in new.sizes.iter().chain(old.sizes.iter()) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = old.sizes.get(name).map_or(0, |size| size.value());
+            let new = new.sizes.get(name).map_or(0, |size| size.value());
+            (old != new).then(|| {
+                format!(
+                    "{}: {} -> {} ({})",
+                    name,
+                    units.format(old),
+                    units.format(new),
+                    units.format_delta(new as i64 - old as i64)
+                )
+            })
+        })
+        .collect()
+}
+
+impl Diffs {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Every change as an event, followed by the summary of them all.
+    #[cfg(feature = "serde")]
+    pub fn events(&self) -> impl Iterator<Item = DiffEvent<'_>> {
+        let added = self.added.iter().map(|map| DiffEvent::Added { map });
+        let removed = self.removed.iter().map(|map| DiffEvent::Removed { map });
+        let changed = self
+            .changed
+            .iter()
+            .map(|(old, new)| DiffEvent::Changed { old, new });
+
+        let summary = DiffEvent::Summary {
+            summary: self.summary(),
+        };
+
+        added
+            .chain(removed)
+            .chain(changed)
+            .chain(std::iter::once(summary))
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        let mut summary = DiffSummary::default();
+
+        for m in &self.added {
+            let (rss, pss) = rss_pss(m);
+            summary.added.add(rss, pss);
+        }
+
+        for m in &self.removed {
+            let (rss, pss) = rss_pss(m);
+            summary.removed.add(-rss, -pss);
+        }
+
+        for (old, new) in &self.changed {
+            let (rss, pss) = delta(old, new);
+            summary.changed.add(rss, pss);
+        }
+
+        summary
+    }
+
+    /// Put the changed maps that moved the most first, by Rss and then by Pss.
+    pub fn sort_changed_by_delta(&mut self) {
+        self.changed.sort_by_key(|(old, new)| {
+            let (rss, pss) = delta(old, new);
+            std::cmp::Reverse((rss.abs(), pss.abs()))
+        });
+    }
+}
+
+/// What marks a map as changed when `--diff-on` isn't given.
+pub const DEFAULT_DIFF_ON: &str = "Size,Rss,permissions";
+
+/// What marks a map as changed between two readings, as given to `--diff-on`, eg.
+/// `Pss,Swap,permissions`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DiffConfig {
+    /// The size fields, named as the kernel does.
+    pub fields: Vec<&'static str>,
+    /// Whether a change to the permissions or flags counts.
+    pub permissions: bool,
+    /// The smallest change in kB to one of the fields that counts, from `--min-delta`.
+    pub min_delta: usize,
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        DEFAULT_DIFF_ON.parse().unwrap()
+    }
+}
+
+impl FromStr for DiffConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = DiffConfig {
+            fields: Vec::new(),
+            permissions: false,
+            min_delta: 0,
+        };
+        for name in s.split(',').map(str::trim) {
+            if name.eq_ignore_ascii_case("permissions") {
+                config.permissions = true;
+            } else {
+                config.fields.push(
+                    memory_map::size_field(name)
+                        .map_err(|err| format!("{}, or permissions", err))?,
+                );
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl DiffConfig {
+    /// Whether anything this looks at differs between the two maps. Which maps are the
+    /// same map is up to the caller.
+    pub fn changed(&self, old: &MemoryMap, new: &MemoryMap) -> bool {
+        (self.permissions && !transitions(old, new).is_empty())
+            || self.fields.iter().any(|field| {
+                let size = |m: &MemoryMap| m.sizes.get(field).map_or(0, |size| size.value());
+                let delta = size(old).abs_diff(size(new));
+                delta > 0 && delta >= self.min_delta
+            })
+    }
+}
+
+/// Diff two readings that are each in address order. Maps starting at the same address
+/// are changed if their end address moves or `diff_on` finds a change.
+pub fn diff_sorted(vec1: &[MemoryMap], vec2: &[MemoryMap], diff_on: &DiffConfig) -> Diffs {
     let mut diffs = Diffs::default();
     let mut i = 0;
     let mut j = 0;
@@ -23,8 +270,7 @@ pub fn diff_sorted(vec1: &[MemoryMap], vec2: &[MemoryMap]) -> Diffs {
             if i < vec1.len()
                 && j < vec2.len()
                 && (vec1[i].address_range.to != vec2[j].address_range.to
-                    || vec1[i].size() != vec2[j].size()
-                    || vec1[i].rss() != vec2[j].rss())
+                    || diff_on.changed(&vec1[i], &vec2[j]))
             {
                 diffs.changed.push((vec1[i].clone(), vec2[j].clone()))
             }
@@ -47,3 +293,277 @@ pub fn diff_sorted(vec1: &[MemoryMap], vec2: &[MemoryMap]) -> Diffs {
 
     diffs
 }
+
+/// Diff the maps of two different processes, such as two replicas of a service. Their
+/// addresses are randomised, so maps are matched on their path and offset instead, with
+/// maps sharing both, such as anonymous ones, matched in the order they come. Matched maps
+/// are changed if `diff_on` finds a change.
+pub fn diff_by_path(old: &[MemoryMap], new: &[MemoryMap], diff_on: &DiffConfig) -> Diffs {
+    let key = |m: &MemoryMap| (m.path.clone(), m.offset);
+    let mut unmatched = BTreeMap::<_, VecDeque<&MemoryMap>>::new();
+    for m in old {
+        unmatched.entry(key(m)).or_default().push_back(m);
+    }
+
+    let mut diffs = Diffs::default();
+    for m in new {
+        match unmatched.get_mut(&key(m)).and_then(|maps| maps.pop_front()) {
+            Some(matched) => {
+                if diff_on.changed(matched, m) {
+                    diffs.changed.push((matched.clone(), m.clone()));
+                }
+            }
+            None => diffs.added.push(m.clone()),
+        }
+    }
+    diffs.removed = unmatched.into_values().flatten().cloned().collect();
+    diffs.removed.sort();
+    diffs
+}
+
+/// Diff readings of several processes, keyed by PID. Each process is diffed on its own,
+/// as the same addresses in different processes are different maps.
+pub fn diff_processes(
+    old: &BTreeMap<usize, Vec<MemoryMap>>,
+    new: &BTreeMap<usize, Vec<MemoryMap>>,
+    diff_on: &DiffConfig,
+) -> Diffs {
+    let mut diffs = Diffs::default();
+    let pids = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    for pid in pids {
+        let process = diff_sorted(
+            old.get(pid).map_or(&[][..], Vec::as_slice),
+            new.get(pid).map_or(&[][..], Vec::as_slice),
+            diff_on,
+        );
+        diffs.added.extend(process.added);
+        diffs.removed.extend(process.removed);
+        diffs.changed.extend(process.changed);
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_map::Numeric;
+
+    fn map(range: &str, rss: usize) -> MemoryMap {
+        map_with(range, "rw-p", rss)
+    }
+
+    fn map_with(range: &str, permissions: &str, rss: usize) -> MemoryMap {
+        format!(
+            "{} {} 00000000 00:00 0 \nSize: {} kB\nRss: {} kB\nPss: {} kB\nVmFlags: rd wr\n",
+            range, permissions, rss, rss, rss
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_events() {
+        let old = vec![map("1000-2000", 4)];
+        let new = vec![map("1000-2000", 4), map("4000-5000", 8)];
+        let diffs = diff_sorted(&old, &new, &DiffConfig::default());
+
+        let events = diffs
+            .events()
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(2, events.len());
+        assert_eq!("added", events[0]["event"]);
+        assert_eq!("summary", events[1]["event"]);
+        assert_eq!(1, events[1]["added"]["maps"]);
+        assert_eq!(8, events[1]["added"]["rss"]);
+
+        let unchanged = diff_sorted(&old, &old, &DiffConfig::default());
+        assert_eq!(1, unchanged.events().count());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let old = vec![map("1000-2000", 4), map("2000-3000", 8)];
+        let new = vec![map("1000-2000", 12), map("4000-5000", 4)];
+        let diffs = diff_sorted(&old, &new, &DiffConfig::default());
+
+        let json = serde_json::to_string(&diffs).unwrap();
+        assert_eq!(diffs, serde_json::from_str(&json).unwrap());
+
+        let msgpack = rmp_serde::to_vec(&diffs).unwrap();
+        assert_eq!(diffs, rmp_serde::from_slice(&msgpack).unwrap());
+    }
+
+    #[test]
+    fn test_diff_config() {
+        let config = "pss,Swap,permissions".parse::<DiffConfig>().unwrap();
+        assert_eq!(vec!["Pss", "Swap"], config.fields);
+        assert!(config.permissions);
+        assert!("rss,nonsense".parse::<DiffConfig>().is_err());
+
+        let old = vec![map("1000-2000", 4), map_with("2000-3000", "rw-p", 4)];
+        let new = vec![map("1000-2000", 8), map_with("2000-3000", "r-xp", 4)];
+        let only_swap = "swap".parse().unwrap();
+        assert!(diff_sorted(&old, &new, &only_swap).is_empty());
+        let only_permissions = "permissions".parse().unwrap();
+        assert_eq!(
+            vec![(old[1].clone(), new[1].clone())],
+            diff_sorted(&old, &new, &only_permissions).changed
+        );
+
+        let mut rss = "rss".parse::<DiffConfig>().unwrap();
+        assert_eq!(1, diff_sorted(&old, &new, &rss).changed.len());
+        rss.min_delta = 8;
+        assert!(diff_sorted(&old, &new, &rss).is_empty());
+    }
+
+    #[test]
+    fn test_permission_change() {
+        let old = vec![map("1000-2000", 4), map_with("2000-3000", "rw-p", 4)];
+        let new = vec![map("1000-2000", 4), map_with("2000-3000", "r-xp", 4)];
+
+        let diffs = diff_sorted(&old, &new, &DiffConfig::default());
+
+        assert!(diffs.added.is_empty());
+        assert!(diffs.removed.is_empty());
+        assert_eq!(1, diffs.changed.len());
+        let (a, b) = &diffs.changed[0];
+        assert_eq!(0x2000, b.address_range.from);
+        assert_eq!(vec!["perms: rw-p -> r-xp".to_string()], transitions(a, b));
+    }
+
+    #[test]
+    fn test_field_changes() {
+        let old = map("1000-2000", 4);
+        let mut new = map("1000-2000", 4);
+        new.sizes.rss = Some(Numeric::Kb(1028));
+        new.sizes.swap = Some(Numeric::Kb(8));
+        assert_eq!(
+            vec![
+                "Rss: 4 kB -> 1028 kB (+1024 kB)".to_string(),
+                "Swap: 0 kB -> 8 kB (+8 kB)".to_string()
+            ],
+            field_changes(&old, &new, Units::Kb)
+        );
+        assert!(field_changes(&old, &old, Units::Kb).is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let old = vec![
+            map("1000-2000", 4),
+            map("2000-3000", 4),
+            map("3000-4000", 8),
+        ];
+        let new = vec![
+            map("2000-3000", 4),
+            map("3000-5000", 12),
+            map("5000-6000", 16),
+        ];
+
+        let summary = diff_sorted(&old, &new, &DiffConfig::default()).summary();
+
+        assert_eq!(
+            DiffCount {
+                maps: 1,
+                rss: 16,
+                pss: 16
+            },
+            summary.added
+        );
+        assert_eq!(
+            DiffCount {
+                maps: 1,
+                rss: -4,
+                pss: -4
+            },
+            summary.removed
+        );
+        assert_eq!(
+            DiffCount {
+                maps: 1,
+                rss: 4,
+                pss: 4
+            },
+            summary.changed
+        );
+    }
+
+    #[test]
+    fn test_sort_changed_by_delta() {
+        let old = vec![
+            map("1000-2000", 4),
+            map("2000-3000", 64),
+            map("3000-4000", 8),
+        ];
+        let new = vec![
+            map("1000-2000", 8),
+            map("2000-3000", 4),
+            map("3000-4000", 16),
+        ];
+
+        let mut diffs = diff_sorted(&old, &new, &DiffConfig::default());
+        diffs.sort_changed_by_delta();
+
+        let order = diffs
+            .changed
+            .iter()
+            .map(|(_, new)| new.address_range.from)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0x2000, 0x3000, 0x1000], order);
+    }
+
+    #[test]
+    fn test_diff_by_path() {
+        let with_path = |range: &str, path: &str, rss: usize| {
+            let mut m = map(range, rss);
+            m.path = Some(path.to_string());
+            m
+        };
+        let old = vec![
+            with_path("1000-2000", "/usr/lib/libc.so.6", 4),
+            map("2000-3000", 4),
+            map("3000-4000", 8),
+            with_path("4000-5000", "/usr/lib/libm.so.6", 4),
+        ];
+        let new = vec![
+            with_path("a000-b000", "/usr/lib/libc.so.6", 4),
+            map("b000-c000", 4),
+            map("c000-d000", 12),
+            with_path("d000-e000", "/usr/lib/libz.so.1", 4),
+        ];
+
+        let diffs = diff_by_path(&old, &new, &DiffConfig::default());
+        assert_eq!(
+            vec![with_path("d000-e000", "/usr/lib/libz.so.1", 4)],
+            diffs.added
+        );
+        assert_eq!(
+            vec![with_path("4000-5000", "/usr/lib/libm.so.6", 4)],
+            diffs.removed
+        );
+        assert_eq!(
+            vec![(map("3000-4000", 8), map("c000-d000", 12))],
+            diffs.changed
+        );
+    }
+
+    #[test]
+    fn test_diff_processes() {
+        let old = BTreeMap::from([(1, vec![map("1000-2000", 4)])]);
+        let new = BTreeMap::from([
+            (1, vec![map("1000-2000", 8)]),
+            (2, vec![map("1000-2000", 4)]),
+        ]);
+
+        let diffs = diff_processes(&old, &new, &DiffConfig::default());
+        assert_eq!(vec![map("1000-2000", 4)], diffs.added);
+        assert!(diffs.removed.is_empty());
+        assert_eq!(
+            vec![(map("1000-2000", 4), map("1000-2000", 8))],
+            diffs.changed
+        );
+    }
+}
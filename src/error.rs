@@ -0,0 +1,112 @@
+//! Why shmaps couldn't do what it was asked, worded to say what to do about it.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ShmapsError {
+    /// Reading a process that exits part way through fails in all sorts of ways, so
+    /// this is given in place of whatever error came out of it.
+    #[error("Process {pid} isn't running, it may have exited")]
+    ProcessGone { pid: usize },
+
+    #[error("No process is named {name}")]
+    NoProcessNamed { name: String },
+
+    #[error(
+        "{} processes are named {name} ({}), pick one with --pid or take them all with --all-matches",
+        pids.len(),
+        pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    AmbiguousName { name: String, pids: Vec<usize> },
+
+    #[error("No container has the ID or name {container}")]
+    NoContainer { container: String },
+
+    #[error(
+        "{} containers have IDs starting {container} ({}), give more of the ID",
+        ids.len(),
+        ids.join(", ")
+    )]
+    AmbiguousContainer { container: String, ids: Vec<String> },
+
+    #[error(
+        "Permission denied reading {}, another user's process can only be read as root or with CAP_SYS_PTRACE",
+        path.display()
+    )]
+    Permission { path: PathBuf },
+
+    #[error("Failed to {action} {}: {source}", path.display())]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("Failed to record to {}: {source}", path.display())]
+    Database {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+
+    #[error("Failed to parse {what}: {message}")]
+    Parse { what: String, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, ShmapsError>;
+
+impl ShmapsError {
+    /// Reading `path` failed.
+    pub fn read(path: &Path, err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => ShmapsError::Permission {
+                path: path.to_path_buf(),
+            },
+            _ => ShmapsError::io("read", path, err),
+        }
+    }
+
+    pub fn io(action: &'static str, path: &Path, source: io::Error) -> Self {
+        ShmapsError::Io {
+            action,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn database(path: &Path, source: rusqlite::Error) -> Self {
+        ShmapsError::Database {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse(what: impl Into<String>, message: impl ToString) -> Self {
+        ShmapsError::Parse {
+            what: what.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let path = Path::new("/proc/1/smaps");
+        assert!(matches!(
+            ShmapsError::read(path, io::ErrorKind::PermissionDenied.into()),
+            ShmapsError::Permission { .. }
+        ));
+        assert_eq!(
+            "Failed to read /proc/1/smaps: entity not found",
+            ShmapsError::read(path, io::ErrorKind::NotFound.into()).to_string()
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Shell style globs for `--path-filter` and `--exclude`, eg. `lib*.so*`.
+
+use regex::Regex;
+
+/// A glob, matched against the whole path if it has a `/` in it and against the file
+/// name otherwise, so `libc*` finds `/usr/lib/libc.so.6`. `*` and `?` don't cross a
+/// `/`, `**` does, and `[...]` is a set of characters.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    regex: Regex,
+    whole_path: bool,
+}
+
+impl Glob {
+    pub fn parse(pattern: &str) -> Result<Glob, String> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    regex.push_str(".*");
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    let mut set = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') if !set.is_empty() => break,
+                            Some(c) => set.push(c),
+                            None => return Err(format!("unclosed [ in {}", pattern)),
+                        }
+                    }
+                    // `[!...]` is the shell's way of writing `[^...]`.
+                    let set = match set.strip_prefix('!') {
+                        Some(rest) => format!("^{}", rest),
+                        None => set,
+                    };
+                    regex.push('[');
+                    regex.push_str(
+                        &set.replace('\\', "\\\\")
+                            .replace('[', "\\[")
+                            .replace(']', "\\]"),
+                    );
+                    regex.push(']');
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Glob {
+            regex: Regex::new(&regex).map_err(|err| err.to_string())?,
+            whole_path: pattern.contains('/'),
+        })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path = match self.whole_path {
+            true => path,
+            false => path.rsplit('/').next().unwrap_or(path),
+        };
+        self.regex.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let matches = |pattern, path| Glob::parse(pattern).unwrap().matches(path);
+        assert!(matches("lib*.so*", "/usr/lib/x86_64-linux-gnu/libc.so.6"));
+        assert!(!matches("lib*.so*", "/usr/bin/bash"));
+        assert!(matches("/usr/lib/*", "/usr/lib/libm.so.6"));
+        assert!(!matches(
+            "/usr/lib/*",
+            "/usr/lib/x86_64-linux-gnu/libm.so.6"
+        ));
+        assert!(matches(
+            "/usr/**.so.6",
+            "/usr/lib/x86_64-linux-gnu/libm.so.6"
+        ));
+        assert!(matches("libc.so.?", "/lib/libc.so.6"));
+        assert!(matches("[[]heap]", "[heap]"));
+        assert!(matches("lib[!c]*", "/lib/libm.so.6"));
+        assert!(!matches("lib[!c]*", "/lib/libc.so.6"));
+        assert!(Glob::parse("lib[c").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    diff::{self, Diffs},
+    memory_map::MemoryMap,
+};
+
+/// How much each map's Rss has moved over a watch session, keyed by its identity.
+#[derive(Default)]
+pub struct Growth {
+    maps: HashMap<u64, (MemoryMap, i64)>,
+    started: bool,
+}
+
+impl Growth {
+    /// Add the changes from one interval onto the running totals. Everything is added
+    /// on the first reading, which isn't growth, so that one only starts the session.
+    pub fn record(&mut self, diffs: &Diffs) {
+        if !std::mem::replace(&mut self.started, true) {
+            return;
+        }
+
+        for m in &diffs.added {
+            self.add(m, m.rss().unwrap_or_default() as i64);
+        }
+
+        for m in &diffs.removed {
+            self.add(m, -(m.rss().unwrap_or_default() as i64));
+        }
+
+        for (old, new) in &diffs.changed {
+            self.add(new, diff::delta(old, new).0);
+        }
+    }
+
+    fn add(&mut self, m: &MemoryMap, delta: i64) {
+        let entry = self
+            .maps
+            .entry(m.identity())
+            .or_insert_with(|| (m.clone(), 0));
+        entry.0 = m.clone();
+        entry.1 += delta;
+    }
+
+    /// The `n` maps that grew the most, with their growth in kB.
+    pub fn top(&self, n: usize) -> Vec<(&MemoryMap, i64)> {
+        let mut growers = self
+            .maps
+            .values()
+            .filter(|(_, delta)| *delta > 0)
+            .map(|(m, delta)| (m, *delta))
+            .collect::<Vec<_>>();
+        growers.sort_by_key(|(m, delta)| (std::cmp::Reverse(*delta), m.address_range.from));
+        growers.truncate(n);
+        growers
+    }
+}
+
+/// The Rss of each map over the last few readings, keyed by its identity.
+pub struct History {
+    intervals: usize,
+    rss: HashMap<u64, VecDeque<usize>>,
+}
+
+impl History {
+    /// Keep enough readings to look back over `intervals` intervals.
+    pub fn new(intervals: usize) -> Self {
+        Self {
+            intervals,
+            rss: HashMap::new(),
+        }
+    }
+
+    /// Add a reading, forgetting the maps that have gone.
+    pub fn record(&mut self, memory_map: &[MemoryMap]) {
+        let mut seen = HashSet::new();
+        for m in memory_map {
+            let identity = m.identity();
+            seen.insert(identity);
+
+            let rss = self.rss.entry(identity).or_default();
+            rss.push_back(m.rss().unwrap_or_default());
+            if rss.len() > self.intervals + 1 {
+                rss.pop_front();
+            }
+        }
+        self.rss.retain(|identity, _| seen.contains(identity));
+    }
+
+    /// Whether the map's Rss went up in every one of the intervals.
+    pub fn is_monotonic(&self, m: &MemoryMap) -> bool {
+        self.rss.get(&m.identity()).is_some_and(|rss| {
+            rss.len() == self.intervals + 1
+                && rss.iter().zip(rss.iter().skip(1)).all(|(a, b)| a < b)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(rss: usize) -> MemoryMap {
+        map_at(0x1000, rss)
+    }
+
+    fn map_at(from: usize, rss: usize) -> MemoryMap {
+        format!(
+            "{:x}-{:x} rw-p 00000000 00:00 0 \nRss: {} kB\nVmFlags: rd wr\n",
+            from,
+            from + 0x1000,
+            rss
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_growth() {
+        let mut growth = Growth::default();
+
+        growth.record(&Diffs {
+            added: vec![map_at(0x1000, 8), map_at(0x3000, 4)],
+            ..Default::default()
+        });
+        assert!(growth.top(5).is_empty());
+
+        growth.record(&Diffs {
+            added: vec![map_at(0x5000, 12)],
+            changed: vec![
+                (map_at(0x1000, 8), map_at(0x1000, 20)),
+                (map_at(0x3000, 4), map_at(0x3000, 2)),
+            ],
+            ..Default::default()
+        });
+        let top = |growth: &Growth, n| {
+            growth
+                .top(n)
+                .into_iter()
+                .map(|(m, delta)| (m.address_range.from, delta))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(0x1000, 12), (0x5000, 12)], top(&growth, 5));
+        assert_eq!(vec![(0x1000, 12)], top(&growth, 1));
+
+        growth.record(&Diffs {
+            removed: vec![map_at(0x5000, 12)],
+            changed: vec![(map_at(0x3000, 2), map_at(0x3000, 10))],
+            ..Default::default()
+        });
+        assert_eq!(vec![(0x1000, 12), (0x3000, 6)], top(&growth, 5));
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let mut history = History::new(3);
+
+        history.record(&[map(4)]);
+        history.record(&[map(8)]);
+        history.record(&[map(12)]);
+        assert!(!history.is_monotonic(&map(12)));
+
+        history.record(&[map(16)]);
+        assert!(history.is_monotonic(&map(16)));
+
+        history.record(&[map(16)]);
+        assert!(!history.is_monotonic(&map(16)));
+
+        history.record(&[]);
+        history.record(&[map(20)]);
+        assert!(!history.is_monotonic(&map(20)));
+    }
+
+    #[test]
+    fn test_monotonic_by_process() {
+        let worker = |pid, rss| MemoryMap {
+            pid: Some(pid),
+            ..map(rss)
+        };
+        let mut history = History::new(1);
+
+        history.record(&[worker(1, 4), worker(2, 16)]);
+        history.record(&[worker(1, 8), worker(2, 12)]);
+        assert!(history.is_monotonic(&worker(1, 8)));
+        assert!(!history.is_monotonic(&worker(2, 12)));
+    }
+}
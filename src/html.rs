@@ -0,0 +1,260 @@
+//! `shmaps report`, a reading as a single HTML page that needs nothing else to view: a
+//! table of every map, the files rolled up, and bars of how the Rss splits into shared
+//! and private, clean and dirty. Clicking a column heading sorts by it.
+
+use std::io::{self, Write};
+
+use crate::{
+    memory_map::{MappingKind, MemoryMap},
+    totals,
+    units::Units,
+};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 2px 8px; border-bottom: 1px solid #ddd; }
+th { cursor: pointer; background: #eee; position: sticky; top: 0; }
+td.number { text-align: right; font-family: monospace; }
+.bar { display: flex; width: 40em; height: 1.5em; margin-bottom: 0.5em; }
+.bar div { overflow: hidden; white-space: nowrap; font-size: small; padding-left: 4px; }
+";
+
+/// Sort on the `data-value` of numeric cells, biggest first, and on the text otherwise.
+const SCRIPT: &str = "
+document.querySelectorAll('th').forEach((th, column) => th.addEventListener('click', () => {
+  const body = th.closest('table').tBodies[0];
+  const key = row => { const cell = row.cells[column]; return cell.dataset.value ?? cell.textContent; };
+  const numeric = th.classList.contains('number');
+  const rows = Array.from(body.rows).sort((a, b) =>
+    numeric ? key(b) - key(a) : key(a).localeCompare(key(b)));
+  const reverse = th.dataset.sorted === 'yes';
+  if (reverse) rows.reverse();
+  th.closest('tr').querySelectorAll('th').forEach(other => delete other.dataset.sorted);
+  th.dataset.sorted = reverse ? 'reversed' : 'yes';
+  rows.forEach(row => body.appendChild(row));
+}));
+";
+
+const COLOURS: &[&str] = &["#4e79a7", "#f28e2b", "#59a14f", "#e15759"];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Table<'a> {
+    headings: &'a [&'a str],
+    /// How many of the columns on the right are numbers.
+    numbers: usize,
+}
+
+impl Table<'_> {
+    fn start(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "<table><thead><tr>")?;
+        let texts = self.headings.len() - self.numbers;
+        for (index, heading) in self.headings.iter().enumerate() {
+            let class = if index < texts {
+                ""
+            } else {
+                " class=\"number\""
+            };
+            writeln!(writer, "<th{}>{}</th>", class, heading)?;
+        }
+        writeln!(writer, "</tr></thead><tbody>")
+    }
+
+    /// A row of the texts followed by the numbers, each given as the value to sort on
+    /// and how to show it.
+    fn row(writer: &mut impl Write, texts: &[&str], numbers: &[(usize, String)]) -> io::Result<()> {
+        write!(writer, "<tr>")?;
+        for text in texts {
+            write!(writer, "<td>{}</td>", escape(text))?;
+        }
+        for (value, shown) in numbers {
+            write!(
+                writer,
+                "<td class=\"number\" data-value=\"{}\">{}</td>",
+                value, shown
+            )?;
+        }
+        writeln!(writer, "</tr>")
+    }
+
+    fn end(writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "</tbody></table>")
+    }
+}
+
+/// A bar split in proportion to the sizes, with each part labelled.
+fn bar(writer: &mut impl Write, parts: &[(&str, usize)], units: Units) -> io::Result<()> {
+    let total = parts.iter().map(|(_, size)| size).sum::<usize>().max(1);
+    writeln!(writer, "<div class=\"bar\">")?;
+    for ((label, size), colour) in parts.iter().zip(COLOURS.iter().cycle()) {
+        writeln!(
+            writer,
+            "<div style=\"width: {:.2}%; background: {}\" title=\"{}: {}\">{}</div>",
+            *size as f64 * 100.0 / total as f64,
+            colour,
+            label,
+            units.format(*size),
+            label
+        )?;
+    }
+    writeln!(writer, "</div>")
+}
+
+fn sum(memory_map: &[MemoryMap], field: &str) -> usize {
+    memory_map
+        .iter()
+        .filter_map(|m| m.sizes.get(field))
+        .map(|size| size.value())
+        .sum()
+}
+
+/// Write the page for the maps, headed by `title`.
+pub fn write(
+    mut writer: impl Write,
+    title: &str,
+    memory_map: &[MemoryMap],
+    units: Units,
+) -> io::Result<()> {
+    let writer = &mut writer;
+    let title = escape(title);
+    writeln!(
+        writer,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head><body>",
+        title, STYLE
+    )?;
+    writeln!(writer, "<h1>{}</h1>", title)?;
+
+    let total = totals::totals(memory_map);
+    writeln!(
+        writer,
+        "<p>{} maps, Rss {}, Pss {}, Uss {}, Swap {}</p>",
+        total.maps,
+        units.format(total.rss),
+        units.format(total.pss),
+        units.format(total.uss),
+        units.format(total.swap)
+    )?;
+
+    writeln!(writer, "<h2>Breakdown of Rss</h2>")?;
+    let shared_clean = sum(memory_map, "Shared_Clean");
+    let shared_dirty = sum(memory_map, "Shared_Dirty");
+    let private_clean = sum(memory_map, "Private_Clean");
+    let private_dirty = sum(memory_map, "Private_Dirty");
+    bar(
+        writer,
+        &[("Shared", total.shared), ("Private", total.uss)],
+        units,
+    )?;
+    bar(
+        writer,
+        &[
+            ("Clean", shared_clean + private_clean),
+            ("Dirty", shared_dirty + private_dirty),
+        ],
+        units,
+    )?;
+
+    writeln!(writer, "<h2>Files</h2>")?;
+    let table = Table {
+        headings: &["Path", "Maps", "Size", "Rss", "Pss", "Swap"],
+        numbers: 5,
+    };
+    table.start(writer)?;
+    let mut files = totals::group_by(memory_map, |m| {
+        (m.kind() == MappingKind::File).then(|| m.path.clone().unwrap_or_default())
+    })
+    .into_iter()
+    .filter_map(|(path, totals)| Some((path?, totals)))
+    .collect::<Vec<_>>();
+    files.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.rss));
+    for (path, totals) in files {
+        let sizes = [totals.size, totals.rss, totals.pss, totals.swap]
+            .map(|size| (size, units.format(size)));
+        let mut numbers = vec![(totals.maps, totals.maps.to_string())];
+        numbers.extend(sizes);
+        Table::row(writer, &[&path], &numbers)?;
+    }
+    Table::end(writer)?;
+
+    writeln!(writer, "<h2>Maps</h2>")?;
+    let table = Table {
+        headings: &[
+            "Address",
+            "Perms",
+            "Kind",
+            "Path",
+            "Size",
+            "Rss",
+            "Pss",
+            "Private dirty",
+            "Swap",
+        ],
+        numbers: 5,
+    };
+    table.start(writer)?;
+    for m in memory_map {
+        let address = format!("{:x}-{:x}", m.address_range.from, m.address_range.to);
+        let sizes = ["Size", "Rss", "Pss", "Private_Dirty", "Swap"].map(|field| {
+            let size = m.sizes.get(field).map_or(0, |size| size.value());
+            (size, units.format(size))
+        });
+        Table::row(
+            writer,
+            &[
+                &address,
+                &m.permissions.to_string(),
+                &m.kind().to_string(),
+                m.path.as_deref().unwrap_or_default(),
+            ],
+            &sizes,
+        )?;
+    }
+    Table::end(writer)?;
+
+    writeln!(writer, "<script>{}</script></body></html>", SCRIPT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let memory_map = [
+            "7f3d2c21b000-7f3d2c21e000 r-xp 00000000 fe:00 1234 /opt/<a>&b.so
+Size:                 12 kB
+Rss:                   8 kB
+Shared_Clean:          8 kB
+VmFlags: rd ex
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+            "7f3d2c21e000-7f3d2c21f000 rw-p 00000000 00:00 0
+Rss:                   4 kB
+Private_Dirty:         4 kB
+VmFlags: rd wr
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+        ];
+
+        let mut out = Vec::new();
+        write(&mut out, "PID 1 (init)", &memory_map, Units::Kb).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("<title>PID 1 (init)</title>"));
+        assert!(out.contains(
+            "<td>/opt/&lt;a&gt;&amp;b.so</td><td class=\"number\" data-value=\"1\">1</td>"
+        ));
+        assert!(out.contains(
+            "<td>7f3d2c21e000-7f3d2c21f000</td><td>rw-p</td><td>anonymous</td><td></td>"
+        ));
+        assert!(out.contains("width: 66.67%; background: #4e79a7\" title=\"Shared: 8 kB\""));
+    }
+}
@@ -0,0 +1,100 @@
+use nix::{
+    libc,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        termios::{self, LocalFlags, SetArg, Termios},
+    },
+    unistd,
+};
+use std::{
+    io::{self, IsTerminal},
+    os::fd::{AsFd, AsRawFd},
+    sync::OnceLock,
+    time::Duration,
+};
+
+/// The signals that would otherwise end the process with the terminal left without echo.
+const SIGNALS: [Signal; 3] = [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTERM];
+
+/// The terminal as it was, for `restore` to put back. Kept as a plain `libc::termios`,
+/// since a `Termios` can't be shared with a signal handler.
+static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+/// Put the terminal back and die of the signal as if it had never been caught. Only
+/// calls what's safe in a signal handler.
+extern "C" fn restore(signal: libc::c_int) {
+    if let Some(original) = ORIGINAL.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+    }
+    // SA_RESETHAND has already put back the default action.
+    unsafe { libc::raise(signal) };
+}
+
+/// Reads single key presses from the terminal without waiting for enter. The terminal
+/// is put back how it was when this is dropped, or if the process is killed by Ctrl-C.
+pub struct Keys {
+    original: Termios,
+    handlers: Vec<(Signal, SigAction)>,
+}
+
+impl Keys {
+    /// Returns `None` if stdin isn't a terminal.
+    pub fn new() -> Option<Self> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return None;
+        }
+
+        let original = termios::tcgetattr(stdin.as_fd()).ok()?;
+        let _ = ORIGINAL.set(original.clone().into());
+
+        let restore = SigAction::new(
+            SigHandler::Handler(restore),
+            SaFlags::SA_RESETHAND,
+            SigSet::empty(),
+        );
+        let handlers = SIGNALS
+            .iter()
+            .filter_map(|&signal| {
+                Some((signal, unsafe { signal::sigaction(signal, &restore) }.ok()?))
+            })
+            .collect();
+
+        // Only line buffering and echo are turned off, so output still scrolls as normal
+        // and Ctrl-C still interrupts.
+        let mut raw = original.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        // Built first so that a failure below still puts the handlers back.
+        let keys = Self { original, handlers };
+        termios::tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &raw).ok()?;
+
+        Some(keys)
+    }
+
+    /// Wait up to `timeout` for a key to be pressed.
+    pub fn wait(&self, timeout: Duration) -> Option<u8> {
+        let stdin = io::stdin();
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+        if poll(&mut fds, timeout).ok()? == 0 {
+            return None;
+        }
+
+        let mut key = [0];
+        match unistd::read(stdin.as_raw_fd(), &mut key) {
+            Ok(1) => Some(key[0]),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(io::stdin().as_fd(), SetArg::TCSANOW, &self.original);
+        for (signal, handler) in &self.handlers {
+            let _ = unsafe { signal::sigaction(*signal, handler) };
+        }
+    }
+}
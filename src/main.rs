@@ -1,107 +1,3142 @@
 use ansi_brush::Style;
-use clap::Parser;
-use nix::unistd;
-use nom::multi::many0;
-use std::{fs, io};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "serde")]
+use diff::DiffEvent;
+use diff::Diffs;
+use error::ShmapsError;
+use memory_map::{MappingKind, MemoryMap, PermissionPattern, Range, VmFlag};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use snapshot::SnapshotFormat;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use units::Units;
 
+mod age;
+mod anonymize;
+mod container;
+mod csv;
 mod diff;
+mod error;
+mod glob;
+mod growth;
+mod html;
+mod keys;
 mod memory_map;
 mod parse;
+mod process;
+mod progress;
+mod report;
+#[cfg(test)]
+mod scratch;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod sparkline;
+mod sqlite;
+mod template;
+mod top;
+mod totals;
+mod tui;
+mod units;
 
-fn get_rss(pid: usize) -> io::Result<usize> {
-    let path = format!("/proc/{}/statm", pid);
-    let contents = fs::read_to_string(path)?;
+/// Captures at least this size, in bytes, show their progress while parsing.
+const PROGRESS_THRESHOLD: usize = 64 * 1024 * 1024;
 
-    let parts: Vec<&str> = contents.split_whitespace().collect();
-    if parts.len() > 1 {
-        // The second value in /proc/[pid]/statm is the RSS in pages
-        let rss_pages = parts[1].parse::<usize>().unwrap();
-        // Convert pages to bytes
-        let page_size = unistd::sysconf(unistd::SysconfVar::PAGE_SIZE)
-            .unwrap()
-            .unwrap_or(4096) as i64;
-        Ok(rss_pages * page_size as usize)
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Unexpected /proc/[pid]/statm format",
-        ))
+/// Upper bound on how often `--every-change` may poll smaps, in polls per second.
+const MAX_POLL_RATE: u64 = 50;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Format {
+    /// Dump every parsed field of each map
+    #[default]
+    Debug,
+    /// Mimic the layout of `pmap`
+    Pmap,
+    /// The whole reading as a JSON array with every field of each map, which --file can
+    /// read back
+    #[cfg(feature = "serde")]
+    Json,
+    /// One JSON object per line
+    #[cfg(feature = "serde")]
+    Jsonl,
+    /// One row per map with a fixed set of columns, for spreadsheets
+    Csv,
+    /// Folded stacks of path components weighted by --weight, for flamegraph.pl
+    Folded,
+    /// The whole reading as MessagePack, which --file can read back
+    #[cfg(feature = "serde")]
+    Msgpack,
+    /// Write the maps back out as smaps, to check nothing was lost parsing them
+    Smaps,
+}
+
+/// A size field the maps can be sorted by, largest first, by the name the kernel gives it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SortKey(&'static str);
+
+impl SortKey {
+    /// The fields the s key goes through while watching.
+    const CYCLE: &[SortKey] = &[
+        SortKey("Size"),
+        SortKey("Rss"),
+        SortKey("Pss"),
+        SortKey("Referenced"),
+        SortKey("Swap"),
+        SortKey("Private_Dirty"),
+    ];
+
+    fn parse(value: &str) -> Result<SortKey, String> {
+        memory_map::size_field(value).map(SortKey)
+    }
+
+    fn value(&self, m: &MemoryMap) -> usize {
+        m.sizes.get(self.0).map_or(0, |size| size.value())
+    }
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// What `shmaps top` orders the processes by, largest first.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum TopSort {
+    /// The memory only the process has, which would be freed if it exited
+    Uss,
+    /// The memory of the process with shared pages split between their sharers
+    #[default]
+    Pss,
+    Rss,
+    Swap,
+}
+
+impl TopSort {
+    fn value(&self, usage: &top::Usage) -> usize {
+        match self {
+            TopSort::Uss => usage.uss,
+            TopSort::Pss => usage.pss,
+            TopSort::Rss => usage.rss,
+            TopSort::Swap => usage.swap,
+        }
+    }
+}
+
+/// Orders for the CHANGED section of a diff.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ChangedSort {
+    /// In address order
+    #[default]
+    Address,
+    /// Biggest change in Rss first
+    Delta,
+}
+
+/// What each reading of a watch is diffed against.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Baseline {
+    /// The reading before it
+    #[default]
+    Previous,
+    /// The first reading, so the diff shows everything since watching began
+    First,
+}
+
+/// The readings of a watch that the next one is diffed against.
+struct Readings {
+    /// The reading before, which growth and ages always go by.
+    last: BTreeMap<usize, Vec<MemoryMap>>,
+    /// The first reading, kept with --baseline first.
+    first: Option<BTreeMap<usize, Vec<MemoryMap>>>,
+    baseline: Baseline,
+}
+
+impl Readings {
+    fn new(baseline: Baseline) -> Self {
+        Self {
+            last: BTreeMap::new(),
+            first: None,
+            baseline,
+        }
+    }
+
+    fn record(&mut self, reading: BTreeMap<usize, Vec<MemoryMap>>) {
+        if self.baseline == Baseline::First && self.first.is_none() {
+            self.first = Some(reading.clone());
+        }
+        self.last = reading;
+    }
+}
+
+/// How the maps in a listing are ordered. While watching this can be changed with keys.
+#[derive(Clone, Copy, Debug)]
+struct Order {
+    sort: Option<SortKey>,
+    reverse: bool,
+}
+
+impl Order {
+    fn apply<T>(&self, items: &mut [T], map: impl Fn(&T) -> &MemoryMap) {
+        if let Some(sort) = self.sort {
+            items.sort_by_key(|item| std::cmp::Reverse(sort.value(map(item))));
+        }
+        if self.reverse {
+            items.reverse();
+        }
+    }
+
+    /// Move on to sorting by the next field, going back to address order after the last.
+    fn next_sort(&mut self) {
+        let keys = SortKey::CYCLE;
+        self.sort = match self.sort {
+            None => keys.first().copied(),
+            Some(sort) => keys.iter().skip_while(|key| **key != sort).nth(1).copied(),
+        };
     }
 }
 
-#[derive(Parser, Debug)]
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Save a reading of the process every --period seconds as a line of JSON, until it
+    /// exits or --count readings have been taken
+    #[cfg(feature = "serde")]
+    Record {
+        /// PID of the process
+        #[arg(short, long)]
+        pid: usize,
+
+        /// Seconds between readings
+        #[arg(long, default_value_t = 1)]
+        period: u64,
+
+        /// The file to append the readings to
+        #[arg(long)]
+        out: PathBuf,
+
+        /// Stop after this many readings
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Save one reading of the process as JSON along with who it is, the kernel and its
+    /// statm Rss, to archive and load back later with --file
+    #[cfg(feature = "serde")]
+    Snapshot {
+        /// PID of the process
+        #[arg(short, long)]
+        pid: usize,
+
+        /// The file to save the snapshot to
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Write a reading of the process as an HTML page, with sortable tables of the maps
+    /// and files and charts of the shared and private, clean and dirty Rss
+    Report {
+        /// PID of the process
+        #[arg(short, long)]
+        pid: usize,
+
+        /// The file to write the page to
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Browse the maps of the process full screen, sorting and searching them and seeing
+    /// every field of the one selected
+    Tui {
+        /// PID of the process
+        #[arg(short, long)]
+        pid: usize,
+
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 1)]
+        period: u64,
+    },
+    /// List the processes using the most memory, refreshing like top
+    Top {
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 2)]
+        period: u64,
+
+        /// How many processes to list
+        #[arg(long, default_value_t = 20)]
+        rows: usize,
+
+        /// What to order the processes by
+        #[arg(long, value_enum, default_value_t)]
+        sort: TopSort,
+
+        /// Stop after this many refreshes
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Print what changed between two saved readings, each a snapshot or a smaps capture
+    Diff {
+        /// The earlier reading
+        old: PathBuf,
+
+        /// The later reading
+        new: PathBuf,
+    },
+    /// Print how the maps of one live process differ from another's, such as a healthy
+    /// and a leaking replica. Maps are matched by path and offset, since their addresses
+    /// differ from process to process
+    Compare {
+        /// The two processes, the first is treated as the old one
+        #[arg(short, long = "pid", required = true)]
+        pids: Vec<usize>,
+    },
+    /// Play back a session saved by record, printing what changed between readings
+    #[cfg(feature = "serde")]
+    Replay {
+        /// The file saved by record
+        file: PathBuf,
+
+        /// How many times faster than it was recorded to play back, 0 for no waiting
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    /// PID of the process
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// PID of the process. Give several, repeated or separated by commas, to show each
+    /// in turn or with --aggregate all together
+    #[arg(id = "pid", short, long = "pid", value_delimiter = ',',
+          required_unless_present_any = ["file", "all", "dir", "name", "cgroup", "container"])]
+    pids: Vec<usize>,
+
+    /// Find the process by its command name rather than its PID
+    #[arg(long, conflicts_with_all = ["pid", "file", "all", "dir"])]
+    name: Option<String>,
+
+    /// Take in the children of the process too, and theirs and so on. When watching, new
+    /// children are picked up every interval
+    #[arg(long, conflicts_with_all = ["file", "all", "dir"])]
+    tree: bool,
+
+    /// With --name, take every process of that name rather than failing if there are
+    /// several
+    #[arg(long, requires = "name")]
+    all_matches: bool,
+
+    /// With several --pid, show the maps of them all as one, eg. for a pool of workers
+    #[arg(long, requires = "pid")]
+    aggregate: bool,
+
+    /// Total up every process we can read and list them by Rss
+    #[arg(long, conflicts_with_all = ["pid", "file"])]
+    all: bool,
+
+    /// Total up every smaps capture in a directory and list them by file
+    #[arg(long, conflicts_with_all = ["pid", "file", "all"])]
+    dir: Option<PathBuf>,
+
+    /// Total up every process in a cgroup, eg. /sys/fs/cgroup/system.slice/nginx.service,
+    /// listing them by Rss with the total of them all
+    #[arg(long, conflicts_with_all = ["pid", "file", "all", "dir", "name", "tree"])]
+    cgroup: Option<PathBuf>,
+
+    /// Show the processes of a Docker, Podman or containerd container as one, found by
+    /// its ID or Docker name, with paths as the container sees them. When watching, new
+    /// processes are picked up every interval
+    #[arg(long, conflicts_with_all = ["pid", "file", "all", "dir", "name", "cgroup"])]
+    container: Option<String>,
+
+    /// With --dir, how many files can fail to parse before giving up and exiting with an
+    /// error. By default every file is tried and the failures listed with the rest
+    #[arg(long, requires = "dir")]
+    max_parse_errors: Option<usize>,
+
+    /// With --all, leave out processes with less than this Rss in kB
+    #[arg(long, requires = "all", default_value_t = 0)]
+    min_rss: usize,
+
+    /// Read the much cheaper maps file, which has no sizes, rather than smaps
+    #[arg(long)]
+    maps: bool,
+
+    /// Where the proc filesystem is mounted, eg. /host/proc from inside a container
+    #[arg(long, default_value = "/proc")]
+    proc_root: PathBuf,
+
+    /// Read a captured smaps file rather than that of a running process, or - for
+    /// standard input
+    #[arg(long, conflicts_with = "pid")]
+    file: Option<PathBuf>,
+
+    /// Don't show the progress of parsing a large --file on stderr
     #[arg(short, long)]
-    pid: usize,
+    quiet: bool,
+
+    /// Treat the file as a log of several captures, split on lines starting with this,
+    /// and print what changed between consecutive captures
+    #[arg(long, requires = "file")]
+    snapshot_delimiter: Option<String>,
+
+    /// Print each map with a template such as "{from:x}-{to:x} {rss}kB {path}". Fields are
+    /// from, to, perms, offset, device, inode, path, flags and any size field, with :x for
+    /// hex and {{ }} for literal braces
+    #[arg(long, conflicts_with_all = ["files", "compact"])]
+    template: Option<template::Template>,
 
-    /// Show just the files
+    /// Comma separated values to show for each map rather than all of them, named as in
+    /// --template, eg. rss,pss,swap,path. Applies to the table, csv, json and jsonl
+    #[arg(long, conflicts_with_all = ["template", "compact", "files"])]
+    fields: Option<template::Fields>,
+
+    /// Roll up the maps of each file into one line with the number of maps, ordered by
+    /// --sort or else Rss
     #[arg(long)]
     files: bool,
 
+    /// Show one line per map with just the main sizes
+    #[arg(long, conflicts_with = "files")]
+    compact: bool,
+
+    /// Show sizes in bytes rather than kB, the same as --units bytes. They're still only
+    /// as precise as the pages the kernel counts in
+    #[arg(long, conflicts_with = "units")]
+    bytes: bool,
+
+    /// The unit to show sizes in. CSV and --fields need whole numbers so stay in kB for
+    /// auto, mb and gb, and JSON and maps shown the way smaps writes them are only ever
+    /// in kB or bytes
+    #[arg(long, value_enum, default_value_t)]
+    units: Units,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+
     /// Show differences every period seconds
     #[arg(long)]
     period: Option<usize>,
+
+    /// Poll rapidly and only show differences when something changed
+    #[arg(long, conflicts_with = "period")]
+    every_change: bool,
+
+    /// Comma separated size fields that mark a map as changed in a diff, eg. pss,swap, and
+    /// permissions for a change to its permissions or flags. A map is always changed if its
+    /// end address moves
+    #[arg(long, default_value = diff::DEFAULT_DIFF_ON)]
+    diff_on: diff::DiffConfig,
+
+    /// Leave out changes to a map smaller than this in each of the --diff-on fields, in kB
+    /// or with a unit, eg. 64K, so pages coming and going don't bury real growth
+    #[arg(long, value_parser = units::parse_size)]
+    min_delta: Option<usize>,
+
+    /// How to order the changed maps in watch mode
+    #[arg(long, value_enum, default_value_t)]
+    changed_sort: ChangedSort,
+
+    /// In watch mode, what each reading is diffed against. With first, slow leaks add up
+    /// rather than getting lost in the noise of each interval
+    #[arg(long, value_enum, default_value_t)]
+    baseline: Baseline,
+
+    /// In watch mode, list the N maps that have grown the most since watching began
+    #[arg(long)]
+    top_growth: Option<usize>,
+
+    /// In watch mode, chart the total Rss and Pss over the last N readings
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    sparkline: Option<u64>,
+
+    /// In watch mode, only show the changed maps whose Rss went up in every one of the
+    /// last K intervals, the likeliest leaks
+    #[arg(long, value_name = "K", value_parser = clap::value_parser!(u64).range(1..))]
+    monotonic_growth: Option<u64>,
+
+    /// In watch mode, show how long each removed or changed map has been around
+    #[arg(long)]
+    age: bool,
+
+    /// In watch mode, run this shell command when the total Rss grows by more than
+    /// --growth-threshold. It gets SHMAPS_PID, SHMAPS_RSS_KB and SHMAPS_RSS_DELTA_KB
+    #[arg(long)]
+    on_growth: Option<String>,
+
+    /// Growth in MB between two readings that triggers --on-growth
+    #[arg(long, default_value_t = 0, requires = "on_growth")]
+    growth_threshold: usize,
+
+    /// Maximum number of polls per second for --every-change
+    #[arg(long, default_value_t = 10)]
+    max_rate: u64,
+
+    /// Only show maps that have mlocked memory
+    #[arg(long)]
+    locked_only: bool,
+
+    /// Only show maps of files that have been deleted, such as libraries replaced by an
+    /// upgrade. With --all, only the processes still holding on to one
+    #[arg(long)]
+    deleted: bool,
+
+    /// Only show maps of these kinds: heap, stack, vdso, vvar, vsyscall, anonymous, file,
+    /// memfd, shm or pseudo
+    #[arg(long, value_delimiter = ',')]
+    kind: Vec<MappingKind>,
+
+    /// Only show anonymous maps (the heap, stacks and maps without a file), where a malloc
+    /// leak would be
+    #[arg(long, conflicts_with = "file_backed")]
+    anon: bool,
+
+    /// Only show maps of files, including memfds and shared memory
+    #[arg(long)]
+    file_backed: bool,
+
+    /// Only show maps that are both writable and executable
+    #[arg(long)]
+    writable_executable: bool,
+
+    /// Only show maps that are writable
+    #[arg(long)]
+    writable: bool,
+
+    /// Only show maps that are executable
+    #[arg(long)]
+    executable: bool,
+
+    /// Only show maps shared with other processes rather than private copy on write
+    #[arg(long)]
+    shared: bool,
+
+    /// Only show maps whose permissions match, either as smaps writes them with ? for
+    /// any, eg. rw-p or r?x?, or as the letters that must be set, eg. wx
+    #[arg(long, value_name = "PERMS")]
+    perm: Option<PermissionPattern>,
+
+    /// Only show maps with any of these comma separated VmFlags, by the two letters the
+    /// kernel writes, eg. lo for locked, ht for hugetlb, mg for mergeable or gd for grows
+    /// down
+    #[arg(long, value_delimiter = ',', value_parser = parse_vm_flag)]
+    vmflag: Vec<VmFlag>,
+
+    /// Leave out maps smaller than this, in kB or with a unit, eg. 1M. This applies to the
+    /// readings before they're compared, so a map growing past it while watching shows as
+    /// added
+    #[arg(long, value_parser = units::parse_size)]
+    min_size: Option<usize>,
+
+    /// Only show maps overlapping the given hex range, eg. 7f0000000000-7f1000000000
+    #[arg(long, value_parser = parse_range)]
+    range: Option<Range>,
+
+    /// Only show the map containing the given hex address
+    #[arg(long, value_parser = parse_address)]
+    address: Option<usize>,
+
+    /// Only show maps whose path matches any of these globs, eg. 'lib*.so*'. A glob
+    /// without a / is matched against the file name, and ** matches across a /
+    #[arg(long, value_name = "GLOB", value_parser = glob::Glob::parse)]
+    path_filter: Vec<glob::Glob>,
+
+    /// Only show maps whose path matches any of these regexes
+    #[arg(long, value_name = "REGEX")]
+    path_regex: Vec<regex::Regex>,
+
+    /// Leave out maps whose path matches any of these globs
+    #[arg(long, value_name = "GLOB", value_parser = glob::Glob::parse)]
+    exclude: Vec<glob::Glob>,
+
+    /// Show how many maps each file has, most first
+    #[arg(long)]
+    segment_counts: bool,
+
+    /// Show Swap and SwapPss for each map with memory swapped out
+    #[arg(long)]
+    swap_report: bool,
+
+    /// Show the Rss and Pss split into categories such as code, heap and stack
+    #[arg(long)]
+    by_category: bool,
+
+    /// Show the Rss and Pss split by what backs each map, such as file, memfd or shm
+    #[arg(long)]
+    by_kind: bool,
+
+    /// Show the Rss and Pss of each file, grouped by device and inode rather than path
+    #[arg(long)]
+    by_inode: bool,
+
+    /// Show an estimate of Pss from Rss by dividing shared memory by --share-count, for
+    /// kernels that don't report Pss
+    #[arg(long, requires = "share_count")]
+    pss_adjust: bool,
+
+    /// How many processes to assume map each shared page for --pss-adjust
+    #[arg(long, requires = "pss_adjust", value_parser = clap::value_parser!(u64).range(1..))]
+    share_count: Option<u64>,
+
+    /// Check the totals summed from smaps against those in smaps_rollup
+    #[arg(long, requires = "pid", conflicts_with_all = ["locked_only", "deleted", "kind", "anon", "file_backed", "writable_executable", "writable", "executable", "shared", "perm", "vmflag", "min_size", "range", "address", "path_filter", "path_regex", "exclude", "limit"])]
+    verify: bool,
+
+    /// Show the unmapped gaps between maps and how much of the address space they cover
+    #[arg(long, conflicts_with = "sort")]
+    gaps: bool,
+
+    /// Show only the Anonymous memory summed across every map
+    #[arg(long)]
+    anon_total: bool,
+
+    /// Sort the maps, or the files with --files, by this size field, largest first, eg.
+    /// rss, swap or private_dirty. Sorted --compact lines start with the map's share of
+    /// the Pss and the share of it the maps so far add up to
+    #[arg(long, value_parser = SortKey::parse)]
+    sort: Option<SortKey>,
+
+    /// List the maps in the opposite order, smallest first with --sort
+    #[arg(long, conflicts_with = "gaps")]
+    reverse: bool,
+
+    /// Only list the N largest maps, or files with --files, by --sort or else Rss. The
+    /// listing ends with a line summing up everything else, except in formats for machines
+    #[arg(long, value_name = "N", conflicts_with_all = ["segment_counts", "swap_report", "pss_adjust", "gaps", "by_category", "by_kind", "by_inode", "anon_total"])]
+    top: Option<usize>,
+
+    /// Warn about unknown fields and maps whose sizes are inconsistent, a sign of a torn or
+    /// corrupt capture
+    #[arg(long)]
+    validate: bool,
+
+    /// Fail on the first sign of unexpected input: an unknown field, a missing field that
+    /// every kernel since 4.14 gives, a map breaking an invariant checked by --validate or
+    /// anything after the last map. Only applies to smaps, and the output waits for the
+    /// whole parse
+    #[arg(long)]
+    strict: bool,
+
+    /// Print the totals after the listing, whichever it is: the Size, Rss, Pss, Uss
+    /// (Private_Clean and Private_Dirty), Shared, Swap and so on summed over the maps
+    #[arg(long)]
+    summary: bool,
+
+    /// Only parse the first N maps of the file, which is quicker on huge captures. This
+    /// applies before any filters or --sort, which only see those N maps
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Show addresses as offsets from the lowest mapped address, the first one listed
+    #[arg(long)]
+    relative_addresses: bool,
+
+    /// Replace home directories and any --anonymize-prefix in paths with placeholders, in
+    /// the maps and in the executable and arguments of the process
+    #[arg(long)]
+    anonymize_paths: bool,
+
+    /// A path prefix to replace with a placeholder when anonymizing paths
+    #[arg(long, requires = "anonymize_paths")]
+    anonymize_prefix: Vec<String>,
+
+    /// Also replace the file names with a hash when anonymizing paths
+    #[arg(long, requires = "anonymize_paths")]
+    hash_basenames: bool,
+
+    /// In watch mode, also write every reading as JSON to a timestamped file in this directory
+    #[cfg(feature = "serde")]
+    #[arg(long)]
+    watch_output_dir: Option<PathBuf>,
+
+    /// The size each map counts for in --format folded. Pss splits shared pages between
+    /// the processes sharing them, so it adds up across processes where Rss doesn't
+    #[arg(long, value_parser = SortKey::parse, default_value = "Pss")]
+    weight: SortKey,
+
+    /// Leave out the row of column names from --format csv
+    #[arg(long)]
+    no_header: bool,
+
+    /// The character between the columns of --format csv
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+
+    /// In watch mode, append the totals of every reading as a row to this CSV file
+    #[arg(long)]
+    log_csv: Option<PathBuf>,
+
+    /// In watch mode, save every map of every reading to this SQLite database, in the
+    /// tables readings and maps
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Show the name and command line of the process above the output
+    #[arg(long, requires = "pid")]
+    show_process: bool,
+
+    /// Only report the total RSS from statm, skipping the smaps parse entirely
+    #[arg(long, requires = "pid")]
+    rss_only: bool,
+
+    /// Memory map the smaps file rather than reading it into a string. The file mustn't be
+    /// truncated or written to meanwhile, which would crash shmaps
+    #[arg(long)]
+    mmap: bool,
 }
 
-fn main() {
-    let args = Args::parse();
-    let smaps_path = format!("/proc/{}/smaps", args.pid);
+/// The raw text of a smaps file, either read into memory or mapped.
+enum Contents {
+    Read(String),
+    Mapped(memmap2::Mmap),
+}
 
-    if let Some(period) = args.period {
-        let mut last_memory_map = Vec::new();
-        loop {
-            let content = fs::read_to_string(&smaps_path).expect("Failed to read smaps file");
+impl Contents {
+    fn as_str(&self) -> &str {
+        match self {
+            Contents::Read(content) => content,
+            // SAFETY: `read_contents` checked the mapping is UTF-8 before making it. Only
+            // a write to the file while it's mapped could change that, the same risk
+            // ruled acceptable there.
+            Contents::Mapped(mmap) => unsafe { std::str::from_utf8_unchecked(mmap) },
+        }
+    }
+}
 
-            let memory_map = many0(parse::parse_memory_map)(&content)
-                .expect("Failed to parse memory map")
-                .1;
+/// Whether the path given is `-`, meaning standard input.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-            let diffs = diff::diff_sorted(&last_memory_map, &memory_map);
-            println!("");
-            println!(
-                "{} - {} mb",
-                chrono::Local::now(),
-                get_rss(args.pid).unwrap() / (1024 * 1024)
-            );
-            println!("ADDED");
-            for m in diffs.added {
-                println!("{}{}{}", "".green(), m, "".reset());
-            }
+fn read_contents(smaps_path: &Path, use_mmap: bool) -> io::Result<Contents> {
+    if is_stdin(smaps_path) {
+        return io::read_to_string(io::stdin()).map(Contents::Read);
+    }
+    if use_mmap {
+        // Files in /proc can't be mapped, so only large captures on disk benefit from this.
+        let file = fs::File::open(smaps_path)?;
+        // SAFETY: Should another process truncate the file while it's mapped, reading the
+        // pages past the new end raises SIGBUS and kills us, and writes to it change the
+        // text under the parse. --mmap is opt in for captures at rest on disk, which
+        // nothing else is writing, so that's accepted for the speed.
+        if let Ok(mmap) = unsafe { memmap2::Mmap::map(&file) } {
+            std::str::from_utf8(&mmap)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            return Ok(Contents::Mapped(mmap));
+        }
+    }
+
+    fs::read_to_string(smaps_path).map(Contents::Read)
+}
+
+/// Read the file the way the command line asked for.
+fn read_file(args: &Args, path: &Path) -> error::Result<Contents> {
+    read_contents(path, args.mmap).map_err(|err| ShmapsError::read(path, err))
+}
+
+/// The resident size of the process from statm, in bytes.
+fn rss(args: &Args, pid: usize) -> error::Result<usize> {
+    process::get_rss(&args.proc_root, pid)
+        .map_err(|err| ShmapsError::read(&process::proc_path(&args.proc_root, pid, "statm"), err))
+}
+
+/// Print why reading the memory map failed and exit. If the process has gone away that
+/// is given as the reason, since it will also have torn or cut short whatever was read.
+fn exit_with(args: &Args, err: ShmapsError) -> ! {
+    let err = match args.pid() {
+        Some(pid) if !process::exists(&args.proc_root, pid) => ShmapsError::ProcessGone { pid },
+        _ => err,
+    };
+    eprintln!("{}", err);
+    std::process::exit(1)
+}
+
+impl Args {
+    /// The process, for everything that reads a single one.
+    fn pid(&self) -> Option<usize> {
+        self.pids.first().copied()
+    }
+
+    /// Snapshots are JSON unless MessagePack was asked for.
+    #[cfg(feature = "serde")]
+    fn snapshot_format(&self) -> SnapshotFormat {
+        if self.format == Format::Msgpack {
+            SnapshotFormat::Msgpack
+        } else {
+            SnapshotFormat::Json
+        }
+    }
+
+    fn watching(&self) -> bool {
+        self.period.is_some() || self.every_change
+    }
+
+    /// How often to reread when watching, and whether only the readings with changes
+    /// are shown.
+    fn watch_interval(&self) -> Option<(Duration, bool)> {
+        if let Some(period) = self.period {
+            Some((Duration::from_secs(period as u64), false))
+        } else if self.every_change {
+            let rate = self.max_rate.clamp(1, MAX_POLL_RATE);
+            Some((Duration::from_millis(1000 / rate), true))
+        } else {
+            None
+        }
+    }
+
+    fn diff_config(&self) -> diff::DiffConfig {
+        diff::DiffConfig {
+            min_delta: self.min_delta.unwrap_or_default(),
+            ..self.diff_on.clone()
+        }
+    }
+
+    fn units(&self) -> Units {
+        if self.bytes {
+            Units::Bytes
+        } else {
+            self.units
+        }
+    }
+    /// Whether any of the filters `keep` applies were given, or --limit, so the maps
+    /// left are only some of the process's.
+    fn filtered(&self) -> bool {
+        self.locked_only
+            || self.deleted
+            || !self.kind.is_empty()
+            || self.anon
+            || self.file_backed
+            || self.writable_executable
+            || self.writable
+            || self.executable
+            || self.shared
+            || self.perm.is_some()
+            || !self.vmflag.is_empty()
+            || self.min_size.is_some()
+            || self.range.is_some()
+            || self.address.is_some()
+            || !self.path_filter.is_empty()
+            || !self.path_regex.is_empty()
+            || !self.exclude.is_empty()
+            || self.limit.is_some()
+    }
+
+    /// Whether the output is JSON lines, which needs serde.
+    #[cfg(feature = "serde")]
+    fn jsonl(&self) -> bool {
+        self.format == Format::Jsonl
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn jsonl(&self) -> bool {
+        false
+    }
+
+    /// Whether the output is meant for another program rather than a person, so
+    /// shouldn't be mixed with progress on the terminal.
+    fn machine_readable(&self) -> bool {
+        !matches!(self.format, Format::Debug | Format::Pmap)
+    }
+
+    /// Whether the output is JSON of either kind.
+    #[cfg(feature = "serde")]
+    fn json(&self) -> bool {
+        matches!(self.format, Format::Json | Format::Jsonl)
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn json(&self) -> bool {
+        false
+    }
+
+    /// Whether the maps can be written out as they are parsed rather than collected first.
+    #[cfg(feature = "serde")]
+    fn streamable(&self) -> bool {
+        self.format == Format::Jsonl
+            && self.sort.is_none()
+            && !self.reverse
+            && self.top.is_none()
+            && self.fields.is_none()
+            && !self.summary
+            && !self.segment_counts
+            && !self.swap_report
+            && !self.anon_total
+            && !self.by_inode
+            && !self.by_category
+            && !self.by_kind
+            && !self.files
+            && !self.pss_adjust
+            && !self.verify
+            && !self.gaps
+    }
+}
+
+fn parse_range(value: &str) -> Result<Range, String> {
+    Range::try_from(value).map_err(|err| err.to_string())
+}
+
+fn parse_address(value: &str) -> Result<usize, String> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    usize::from_str_radix(value, 16).map_err(|err| err.to_string())
+}
+
+/// Any two letters, so flags from kernels newer than this can be asked for too.
+fn parse_vm_flag(value: &str) -> Result<VmFlag, String> {
+    match value.len() == 2 && value.bytes().all(|b| b.is_ascii_lowercase()) {
+        true => Ok(VmFlag::from(value)),
+        false => Err("expected the two letters of a flag, eg. lo".to_string()),
+    }
+}
+
+fn read_memory_map(args: &Args, smaps_path: &Path) -> error::Result<Vec<MemoryMap>> {
+    if let Some(memory_map) = load_snapshot(args)? {
+        return Ok(memory_map);
+    }
+    if args.maps || args.strict || args.mmap || is_stdin(smaps_path) {
+        let contents = read_file(args, smaps_path)?;
+        return memory_maps(args, contents.as_str());
+    }
 
-            println!("REMOVED");
-            for m in diffs.removed {
-                println!("{}{}{}", "".red(), m, "".reset());
+    // Parsed as it's read, so a process with a great many maps doesn't need its whole
+    // smaps read into one string every time.
+    let file = fs::File::open(smaps_path).map_err(|err| ShmapsError::read(smaps_path, err))?;
+    let mut error = None;
+    let parsed = parse::ReadMaps::new(io::BufReader::new(file))
+        .map_while(|m| m.map_err(|err| error = Some(err)).ok());
+    let memory_map = filter_maps(args, parsed).collect();
+
+    match error {
+        Some(parse::ReadError::Io(err)) => Err(ShmapsError::read(smaps_path, err)),
+        Some(parse::ReadError::Parse(err)) => Err(ShmapsError::parse("memory map", err)),
+        None => Ok(memory_map),
+    }
+}
+
+/// Load the --file if it's a saved snapshot rather than a smaps capture.
+#[cfg(feature = "serde")]
+fn load_snapshot(args: &Args) -> error::Result<Option<Vec<MemoryMap>>> {
+    let Some(path) = args.file.as_deref().filter(|path| !is_stdin(path)) else {
+        return Ok(None);
+    };
+    let memory_map = snapshot::load(path).map_err(|err| ShmapsError::read(path, err))?;
+    Ok(memory_map.map(|memory_map| filter_maps(args, memory_map.into_iter()).collect()))
+}
+
+/// Snapshots are only read with serde, so without it the --file is a smaps capture.
+#[cfg(not(feature = "serde"))]
+fn load_snapshot(_args: &Args) -> error::Result<Option<Vec<MemoryMap>>> {
+    Ok(None)
+}
+
+/// Load every map in a snapshot or smaps capture at `path`.
+fn load_maps(args: &Args, path: &Path) -> error::Result<Vec<MemoryMap>> {
+    #[cfg(feature = "serde")]
+    if !is_stdin(path) {
+        if let Some(memory_map) =
+            snapshot::load(path).map_err(|err| ShmapsError::read(path, err))?
+        {
+            return Ok(filter_maps(args, memory_map.into_iter()).collect());
+        }
+    }
+    let contents = read_file(args, path)?;
+    memory_maps(args, contents.as_str())
+}
+
+/// Parse the maps out of the contents, applying the filters and address rewriting
+/// given on the command line.
+fn memory_maps(args: &Args, content: &str) -> error::Result<Vec<MemoryMap>> {
+    let mut error = None;
+    let memory_map = parsed_maps(args, content, &mut error).collect();
+    match error {
+        Some(err) => Err(err),
+        None => Ok(memory_map),
+    }
+}
+
+/// Like `memory_maps`, but the maps come out as they're parsed. They stop at the first
+/// one that can't be parsed, leaving the reason in `error`.
+fn parsed_maps<'a>(
+    args: &'a Args,
+    content: &'a str,
+    error: &'a mut Option<ShmapsError>,
+) -> Box<dyn Iterator<Item = MemoryMap> + 'a> {
+    if args.maps {
+        return match parse::parse_maps(content) {
+            Ok(memory_maps) => filter_maps(args, memory_maps.into_iter()),
+            Err(err) => {
+                *error = Some(ShmapsError::parse("maps", err));
+                Box::new(std::iter::empty())
             }
+        };
+    }
 
-            println!("CHANGED");
-            for (a, b) in diffs.changed {
-                println!("{}{}{}", "".cyan(), a, "".reset());
-                println!("{}{}{}", "".yellow(), b, "".reset());
-                println!("--------");
+    if args.strict {
+        return match parse::parse_all(content, true) {
+            Ok(result) => filter_maps(args, result.maps.into_iter()),
+            Err(err) => {
+                *error = Some(ShmapsError::parse("memory map", err));
+                Box::new(std::iter::empty())
             }
+        };
+    }
+
+    let mut parser = parse::MemoryMaps::new(content);
 
-            last_memory_map = memory_map;
+    let mut progress = (args.file.is_some()
+        && !args.quiet
+        && !args.machine_readable()
+        && content.len() >= PROGRESS_THRESHOLD
+        && io::stderr().is_terminal())
+    .then(|| progress::Progress::new(content.len()));
 
-            std::thread::sleep(std::time::Duration::from_secs(period as u64));
+    let memory_maps = std::iter::from_fn(move || {
+        let memory_map = match parser.next() {
+            Some(Ok(memory_map)) => Some(memory_map),
+            Some(Err(err)) => {
+                *error = Some(ShmapsError::parse("memory map", err));
+                None
+            }
+            None => None,
+        };
+        if let Some(progress) = &mut progress {
+            match memory_map {
+                Some(_) => progress.update(parser.consumed()),
+                None => progress.finish(),
+            }
         }
-    } else {
-        let content = fs::read_to_string(smaps_path).expect("Failed to read smaps file");
+        memory_map
+    });
 
-        let map = many0(parse::parse_memory_map)(&content);
-        match map {
-            Ok((_, memory_map)) => {
-                for m in memory_map {
-                    if args.files {
-                        if let Some(path) = &m.path {
-                            println!("{} {}", path, m.size().unwrap());
-                        }
-                    } else {
-                        println!("{:?}", m)
-                    }
-                }
+    filter_maps(args, memory_maps)
+}
+
+/// Apply the validation, filters and address rewriting given on the command line.
+fn filter_maps<'a>(
+    args: &'a Args,
+    memory_maps: impl Iterator<Item = MemoryMap> + 'a,
+) -> Box<dyn Iterator<Item = MemoryMap> + 'a> {
+    // A container's processes get their paths the way they see them before any of the
+    // filters look at them.
+    let root = args
+        .container
+        .as_ref()
+        .and(args.pid())
+        .and_then(|pid| container::root(&args.proc_root, pid));
+
+    // Warnings are gathered as the maps go past and reported once they've all been read.
+    let mut memory_maps = memory_maps.take(args.limit.unwrap_or(usize::MAX));
+    let mut warnings = Vec::new();
+    let memory_maps = std::iter::from_fn(move || match memory_maps.next() {
+        Some(m) => {
+            if args.validate {
+                warnings.extend(parse::check(&m));
             }
-            Err(err) => eprintln!("Error parsing memory map: {:?}", err),
+            Some(m)
         }
+        None => {
+            report_warnings(args, &std::mem::take(&mut warnings));
+            None
+        }
+    });
+
+    // The kernel lists maps in address order, so the first is the lowest and the rest can
+    // be rewritten as they're read. Any lower one in an edited capture shows as 0.
+    let mut base = None;
+    Box::new(
+        memory_maps
+            .map(move |m| {
+                let base = *base.get_or_insert(m.address_range.from);
+                (base, inside_container(root.as_deref(), m))
+            })
+            .filter(|(_, m)| keep(args, m))
+            .map(move |(base, m)| relative(args, m, base))
+            .map(|m| anonymize(args, m)),
+    )
+}
+
+/// Rewrite the map's path to the one the process sees inside its container, if it's in one.
+fn inside_container(root: Option<&Path>, mut m: MemoryMap) -> MemoryMap {
+    if let Some(inside) = root
+        .zip(m.path.as_deref())
+        .and_then(|(root, path)| container::path_inside(root, path))
+    {
+        m.path = Some(inside);
+    }
+    m
+}
+
+/// How paths are rewritten, if --anonymize-paths was given.
+fn anonymizer(args: &Args) -> Option<anonymize::Anonymizer<'_>> {
+    args.anonymize_paths.then(|| anonymize::Anonymizer {
+        prefixes: &args.anonymize_prefix,
+        hash_basenames: args.hash_basenames,
+    })
+}
+
+/// Rewrite the map's path if --anonymize-paths was given.
+fn anonymize(args: &Args, mut m: MemoryMap) -> MemoryMap {
+    if let Some(anonymizer) = anonymizer(args) {
+        m.path = m.path.map(|path| anonymizer.path(&path));
+    }
+    m
+}
+
+/// The details of the process, anonymized like the maps they're shown with.
+fn read_process_info(args: &Args, pid: usize) -> io::Result<process::ProcessInfo> {
+    let info = process::read_process_info(&args.proc_root, pid)?;
+    Ok(match anonymizer(args) {
+        Some(anonymizer) => anonymizer.process(info),
+        None => info,
+    })
+}
+
+/// Print the warnings to stderr, as JSON lines if that's the output format.
+fn report_warnings(args: &Args, warnings: &[parse::Warning]) {
+    if warnings.is_empty() {
+        return;
+    }
+
+    if args.json() {
+        #[cfg(feature = "serde")]
+        for warning in warnings {
+            eprintln!("{}", serde_json::to_string(warning).unwrap());
+        }
+        return;
+    }
+
+    eprintln!("{} warnings", warnings.len());
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Rewrite the map's range as offsets from `base` if --relative-addresses was given.
+fn relative(args: &Args, mut m: MemoryMap, base: usize) -> MemoryMap {
+    if args.relative_addresses {
+        m.address_range = m.address_range.relative_to(base);
+    }
+    m
+}
+
+/// Whether the map passes the filters given on the command line.
+fn keep(args: &Args, m: &MemoryMap) -> bool {
+    (!args.locked_only || m.is_locked())
+        && (!args.deleted || m.deleted)
+        && (args.kind.is_empty() || args.kind.contains(&m.kind()))
+        && (!args.anon || m.kind().is_anonymous())
+        && (!args.file_backed || m.kind().is_file_backed())
+        && (!args.writable_executable || m.permissions.is_writable_and_executable())
+        && (!args.writable || m.permissions.write)
+        && (!args.executable || m.permissions.execute)
+        && (!args.shared || m.permissions.shared)
+        && args.perm.is_none_or(|perm| perm.matches(&m.permissions))
+        && (args.vmflag.is_empty() || args.vmflag.iter().any(|flag| m.vm_flags.contains(flag)))
+        && args
+            .min_size
+            .is_none_or(|min_size| m.pmap_size() >= min_size)
+        && args
+            .range
+            .as_ref()
+            .is_none_or(|range| range.overlaps(&m.address_range))
+        && args
+            .address
+            .is_none_or(|address| m.address_range.contains(address))
+        && keep_path(args, m.path.as_deref())
+}
+
+/// Whether the path passes --path-filter, --path-regex and --exclude. Maps without a
+/// path only pass if no path has to match.
+fn keep_path(args: &Args, path: Option<&str>) -> bool {
+    let included = args.path_filter.is_empty() && args.path_regex.is_empty();
+    let Some(path) = path else {
+        return included;
+    };
+    (included
+        || args.path_filter.iter().any(|glob| glob.matches(path))
+        || args.path_regex.iter().any(|regex| regex.is_match(path)))
+        && !args.exclude.iter().any(|glob| glob.matches(path))
+}
+
+/// The Rss of the reading `totals` are of. For a single process it's statm's, which is
+/// cheap to read and can't be torn, but statm only covers one process so a group of them
+/// is given the total of their maps.
+fn reading_rss(args: &Args, totals: &totals::Totals) -> error::Result<usize> {
+    match args.pids.as_slice() {
+        [pid] if !args.tree && args.container.is_none() => Ok(rss(args, *pid)? / 1024),
+        _ => Ok(totals.rss),
+    }
+}
+
+/// Print the diffs under a line of the time and the Rss and Uss of the reading they lead up
+/// to.
+fn print_diffs(
+    args: &Args,
+    diffs: &Diffs,
+    totals: &totals::Totals,
+    ages: Option<&age::Ages>,
+) -> error::Result<()> {
+    let units = args.units();
+    let rss = reading_rss(args, totals)?;
+    println!();
+    print!(
+        "{} - {} (USS {})",
+        chrono::Local::now(),
+        units.format(rss),
+        units.format(totals.uss)
+    );
+    match process_info(args) {
+        Some(info) => println!(" - {}", info),
+        None => println!(),
+    }
+    print_diff_sections(units, diffs, ages);
+    Ok(())
+}
+
+/// Print how long the map has been around, if ages are being tracked.
+fn print_age(ages: Option<&age::Ages>, m: &MemoryMap) {
+    if let Some(age) = ages.and_then(|ages| ages.age(m, Instant::now())) {
+        println!("age {}s", age.as_secs());
+    }
+}
+
+fn print_diff_sections(units: Units, diffs: &Diffs, ages: Option<&age::Ages>) {
+    println!("{}", diffs.summary().format(units));
+
+    // Only print the sections that have something in them.
+    if !diffs.added.is_empty() {
+        println!("ADDED");
+        for m in &diffs.added {
+            println!("{}{}{}", "".green(), units.convert(m), "".reset());
+        }
+    }
+
+    if !diffs.removed.is_empty() {
+        println!("REMOVED");
+        for m in &diffs.removed {
+            println!("{}{}{}", "".red(), units.convert(m), "".reset());
+            print_age(ages, m);
+        }
+    }
+
+    if !diffs.changed.is_empty() {
+        println!("CHANGED");
+        for (a, b) in &diffs.changed {
+            println!("{}{}{}", "".cyan(), b.heading(), "".reset());
+            for change in diff::field_changes(a, b, units) {
+                println!("{}{}{}", "".yellow(), change, "".reset());
+            }
+            for transition in diff::transitions(a, b) {
+                println!("{}{}{}", "".magenta(), transition, "".reset());
+            }
+            print_age(ages, b);
+            println!("--------");
+        }
+    }
+}
+
+fn print_top_growth(growth: &growth::Growth, n: usize, units: Units) {
+    let top = growth.top(n);
+    if !top.is_empty() {
+        println!("TOP GROWTH");
+        for (m, delta) in top {
+            let pid = m.pid.map(|pid| format!("PID {} ", pid)).unwrap_or_default();
+            println!(
+                "{} {}{} {} {}",
+                units.format_delta(delta),
+                pid,
+                m.address_range,
+                m.permissions,
+                m.path.as_deref().unwrap_or_default()
+            );
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn print_diff_events(diffs: &Diffs, units: Units) {
+    #[derive(Serialize)]
+    struct Record<'a> {
+        timestamp: String,
+        #[serde(flatten)]
+        event: DiffEvent<'a>,
+    }
+
+    let converted;
+    let diffs = if units != Units::Bytes {
+        diffs
+    } else {
+        let convert =
+            |maps: &[MemoryMap]| maps.iter().map(|m| units.convert(m).into_owned()).collect();
+        converted = Diffs {
+            added: convert(&diffs.added),
+            removed: convert(&diffs.removed),
+            changed: diffs
+                .changed
+                .iter()
+                .map(|(old, new)| {
+                    (
+                        units.convert(old).into_owned(),
+                        units.convert(new).into_owned(),
+                    )
+                })
+                .collect(),
+        };
+        &converted
+    };
+
+    let timestamp = chrono::Local::now().to_rfc3339();
+    for event in diffs.events() {
+        let record = Record {
+            timestamp: timestamp.clone(),
+            event,
+        };
+        println!("{}", serde_json::to_string(&record).unwrap());
+    }
+}
+
+/// Write the reading to `<dir>/smaps-<pid>-<timestamp>.json` (or `.msgpack`), adding a
+/// counter to the name if a file for that timestamp already exists.
+#[cfg(feature = "serde")]
+fn write_snapshot(
+    dir: &Path,
+    pid: Option<usize>,
+    memory_map: &[MemoryMap],
+    format: SnapshotFormat,
+) -> io::Result<PathBuf> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    let stem = match pid {
+        Some(pid) => format!("smaps-{}-{}", pid, timestamp),
+        None => format!("smaps-{}", timestamp),
+    };
+
+    let mut path = dir.join(format!("{}.{}", stem, format.extension()));
+    let mut count = 0;
+    let file = loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => break file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                count += 1;
+                path = dir.join(format!("{}-{}.{}", stem, count, format.extension()));
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    snapshot::write(file, memory_map, format)?;
+    Ok(path)
+}
+
+/// Append a `timestamp,rss_kb,pss_kb,swap_kb,map_count` row to the CSV at `path`,
+/// writing the header first if the file is empty.
+fn append_csv(path: &Path, totals: &totals::Totals) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "timestamp,rss_kb,pss_kb,swap_kb,map_count")?;
+    }
+    writeln!(
+        file,
+        "{},{},{},{},{}",
+        chrono::Local::now().to_rfc3339(),
+        totals.rss,
+        totals.pss,
+        totals.swap,
+        totals.maps
+    )
+}
+
+/// Print what changed between each consecutive capture in a log of captures.
+fn replay_snapshots(args: &Args, content: &str, delimiter: &str) -> error::Result<()> {
+    let mut last_memory_map: Option<Vec<MemoryMap>> = None;
+
+    for (marker, section) in parse::split_snapshots(content, delimiter) {
+        let memory_map = memory_maps(args, section)?;
+
+        if let Some(last_memory_map) = &last_memory_map {
+            print_replayed(args, marker, last_memory_map, &memory_map);
+        }
+
+        last_memory_map = Some(memory_map);
+    }
+    Ok(())
+}
+
+/// Print what changed from one saved reading to the next, under `heading`.
+fn print_replayed(args: &Args, heading: Option<&str>, old: &[MemoryMap], new: &[MemoryMap]) {
+    print_headed_diffs(
+        args,
+        heading,
+        &diff::diff_sorted(old, new, &args.diff_config()),
+    );
+}
+
+/// Print `diffs` as events for jsonl, and as sections under `heading` otherwise.
+fn print_headed_diffs(args: &Args, heading: Option<&str>, diffs: &Diffs) {
+    if args.jsonl() {
+        #[cfg(feature = "serde")]
+        print_diff_events(diffs, args.units());
+    } else {
+        println!();
+        if let Some(heading) = heading {
+            println!("{}", heading);
+        }
+        print_diff_sections(args.units(), diffs, None);
+    }
+}
+
+/// Append a reading of the process to `out` every `interval`.
+#[cfg(feature = "serde")]
+fn record(
+    args: &Args,
+    pid: usize,
+    interval: Duration,
+    out: &Path,
+    count: Option<usize>,
+) -> error::Result<()> {
+    let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(out)
+        .map_err(|err| ShmapsError::io("open", out, err))?;
+
+    for taken in 1.. {
+        if !process::exists(&args.proc_root, pid) {
+            eprintln!("Process {} has exited, stopping the recording", pid);
+            return Ok(());
+        }
+        let recorded = snapshot::Recorded {
+            timestamp: chrono::Local::now().fixed_offset(),
+            process: read_process_info(args, pid).ok(),
+            maps: read_memory_map(args, &smaps_path)?,
+        };
+        let mut line = serde_json::to_string(&recorded).unwrap();
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .map_err(|err| ShmapsError::io("write", out, err))?;
+        eprintln!("Recorded {} readings", taken);
+
+        if count.is_some_and(|count| taken >= count) {
+            break;
+        }
+        std::thread::sleep(interval);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn save_snapshot(args: &Args, pid: usize, out: &Path) -> error::Result<()> {
+    let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+    let snapshot = snapshot::Snapshot {
+        timestamp: chrono::Local::now().fixed_offset(),
+        process: read_process_info(args, pid).map_err(|err| {
+            ShmapsError::read(&process::proc_path(&args.proc_root, pid, "cmdline"), err)
+        })?,
+        kernel: process::kernel_release(&args.proc_root).ok(),
+        statm_rss_kb: process::get_rss(&args.proc_root, pid)
+            .ok()
+            .map(|rss| rss / 1024),
+        maps: read_memory_map(args, &smaps_path)?,
+    };
+
+    let file = fs::File::create(out).map_err(|err| ShmapsError::io("create", out, err))?;
+    let mut writer = io::BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &snapshot)
+        .map_err(io::Error::from)
+        .and_then(|()| writer.flush())
+        .map_err(|err| ShmapsError::io("write", out, err))?;
+    eprintln!(
+        "Saved {} maps of {} to {}",
+        snapshot.maps.len(),
+        snapshot.process,
+        out.display()
+    );
+    Ok(())
+}
+
+fn write_report(args: &Args, pid: usize, out: &Path) -> error::Result<()> {
+    let memory_map = read_memory_map(args, &process::proc_path(&args.proc_root, pid, "smaps"))?;
+    let title = match read_process_info(args, pid) {
+        Ok(info) => info.to_string(),
+        Err(_) => format!("PID {}", pid),
+    };
+
+    let file = fs::File::create(out).map_err(|err| ShmapsError::io("create", out, err))?;
+    let mut writer = io::BufWriter::new(file);
+    html::write(&mut writer, &title, &memory_map, args.units())
+        .and_then(|()| writer.flush())
+        .map_err(|err| ShmapsError::io("write", out, err))?;
+    eprintln!(
+        "Wrote a report of {} maps to {}",
+        memory_map.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+/// Play back a session saved by `record`, waiting between readings for as long as the
+/// recording did, divided by `speed`.
+#[cfg(feature = "serde")]
+fn replay(args: &Args, path: &Path, speed: f64) -> error::Result<()> {
+    let contents = read_file(args, path)?;
+    let mut last: Option<snapshot::Recorded> = None;
+
+    for (index, line) in contents.as_str().lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut recorded = serde_json::from_str::<snapshot::Recorded>(line).map_err(|err| {
+            ShmapsError::parse(format!("line {} of {}", index + 1, path.display()), err)
+        })?;
+        recorded.maps = filter_maps(args, recorded.maps.into_iter()).collect();
+
+        if let Some(last) = &last {
+            if speed > 0.0 {
+                if let Ok(gap) = (recorded.timestamp - last.timestamp).to_std() {
+                    std::thread::sleep(gap.div_f64(speed));
+                }
+            }
+            print_replayed(
+                args,
+                Some(&recorded.timestamp.to_string()),
+                &last.maps,
+                &recorded.maps,
+            );
+        }
+
+        last = Some(recorded);
+    }
+    Ok(())
+}
+
+/// A reading of the watched process, or of each process by PID when watching several,
+/// a --tree or a --container. The tree and container are looked up again every time, to
+/// pick up new processes.
+fn read_watched(args: &Args, smaps_path: &Path) -> error::Result<BTreeMap<usize, Vec<MemoryMap>>> {
+    let list_error = |err| ShmapsError::io("list", &args.proc_root, err);
+    let pids = if let Some(container) = &args.container {
+        container_pids(container)?
+    } else if args.tree {
+        process::tree(&args.proc_root, &args.pids).map_err(list_error)?
+    } else if args.pids.len() > 1 {
+        args.pids.clone()
+    } else {
+        let memory_map = read_memory_map(args, smaps_path)?;
+        return Ok(BTreeMap::from([(
+            args.pid().unwrap_or_default(),
+            memory_map,
+        )]));
+    };
+
+    let mut reading = BTreeMap::new();
+    for pid in pids {
+        let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+        let single = Args {
+            pids: vec![pid],
+            ..args.clone()
+        };
+        match read_memory_map(&single, &smaps_path) {
+            Ok(mut memory_map) => {
+                for m in &mut memory_map {
+                    m.pid = Some(pid);
+                }
+                reading.insert(pid, memory_map);
+            }
+            // Only the processes asked for by PID have to stay, anything else can come
+            // and go between listing and reading it.
+            Err(_)
+                if (args.container.is_some() || !args.pids.contains(&pid))
+                    && !process::exists(&args.proc_root, pid) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(reading)
+}
+
+/// Reread smaps every `interval` and print what changed since the previous read.
+/// With `only_changes` set, intervals where nothing changed print nothing at all.
+fn watch(
+    args: &Args,
+    smaps_path: &Path,
+    interval: Duration,
+    only_changes: bool,
+) -> error::Result<()> {
+    #[cfg(feature = "serde")]
+    if let Some(dir) = &args.watch_output_dir {
+        fs::create_dir_all(dir).map_err(|err| ShmapsError::io("create", dir, err))?;
+    }
+
+    let mut last_memory_map = Vec::new();
+    let mut readings = Readings::new(args.baseline);
+    let diff_on = args.diff_config();
+    let mut growth = growth::Growth::default();
+    let mut ages = age::Ages::default();
+    let mut history = args
+        .monotonic_growth
+        .map(|intervals| growth::History::new(intervals as usize));
+    let mut sparklines = args
+        .sparkline
+        .map(|width| sparkline::Sparklines::new(width as usize));
+    let mut first = true;
+    let mut database = args
+        .record
+        .as_deref()
+        .map(|path| sqlite::Database::open(path).map_err(|err| ShmapsError::database(path, err)))
+        .transpose()?;
+
+    let mut order = Order {
+        sort: args.sort,
+        reverse: args.reverse,
+    };
+    let keys = if args.jsonl() {
+        None
+    } else {
+        keys::Keys::new()
+    };
+    if keys.is_some() {
+        eprintln!("Press s to change the sort field, r to reverse it and q to quit");
+    }
+
+    loop {
+        let reading = read_watched(args, smaps_path)?;
+        let memory_map = reading.values().flatten().cloned().collect::<Vec<_>>();
+
+        #[cfg(feature = "serde")]
+        if let Some(dir) = &args.watch_output_dir {
+            if let Err(err) = write_snapshot(dir, args.pid(), &memory_map, args.snapshot_format()) {
+                eprintln!("Failed to write snapshot to {}: {}", dir.display(), err);
+            }
+        }
+
+        if let Some(path) = &args.log_csv {
+            if let Err(err) = append_csv(path, &totals::totals(&memory_map)) {
+                eprintln!("Failed to log totals to {}: {}", path.display(), err);
+            }
+        }
+
+        if let (Some(database), Some(path)) = (&mut database, &args.record) {
+            let timestamp = chrono::Local::now().fixed_offset();
+            if let Err(err) = database.record(timestamp, args.pid(), &memory_map) {
+                eprintln!("{}", ShmapsError::database(path, err));
+            }
+        }
+
+        if let Some(sparklines) = &mut sparklines {
+            sparklines.record(&totals::totals(&memory_map));
+        }
+
+        let sorted = |mut diffs: Diffs| {
+            order.apply(&mut diffs.added, |m| m);
+            order.apply(&mut diffs.removed, |m| m);
+            if args.changed_sort == ChangedSort::Delta {
+                diffs.sort_changed_by_delta();
+            } else {
+                order.apply(&mut diffs.changed, |(_, new)| new);
+            }
+            diffs
+        };
+        let diffs = sorted(diff::diff_processes(&readings.last, &reading, &diff_on));
+        // Growth and ages still go by each interval, only what's shown is against the
+        // baseline.
+        let against_baseline = match &readings.first {
+            Some(baseline) => sorted(diff::diff_processes(baseline, &reading, &diff_on)),
+            None => diffs.clone(),
+        };
+
+        growth.record(&diffs);
+        // Everything is new on the first reading, which isn't growth.
+        if !first {
+            if let Some(command) = &args.on_growth {
+                let rss = totals::totals(&memory_map).rss;
+                let delta = rss as i64 - totals::totals(&last_memory_map).rss as i64;
+                if delta > (args.growth_threshold * 1024) as i64 {
+                    run_on_growth(command, args.pid(), rss, delta);
+                }
+            }
+        }
+
+        // A map that's just appeared or gone can't have been growing throughout.
+        let monotonic;
+        let shown = match &mut history {
+            Some(history) => {
+                history.record(&memory_map);
+                monotonic = Diffs {
+                    changed: against_baseline
+                        .changed
+                        .iter()
+                        .filter(|(_, new)| history.is_monotonic(new))
+                        .cloned()
+                        .collect(),
+                    ..Diffs::default()
+                };
+                &monotonic
+            }
+            None => &against_baseline,
+        };
+
+        if !only_changes || !shown.is_empty() {
+            if args.jsonl() {
+                #[cfg(feature = "serde")]
+                print_diff_events(shown, args.units());
+            } else {
+                let totals = totals::totals(&memory_map);
+                print_diffs(args, shown, &totals, args.age.then_some(&ages))?;
+                if let Some(sparklines) = &sparklines {
+                    println!("{}", sparklines.format(args.units()));
+                }
+                if let Some(n) = args.top_growth {
+                    print_top_growth(&growth, n, args.units());
+                }
+            }
+        }
+
+        ages.record(&diffs, Instant::now());
+        last_memory_map = memory_map;
+        readings.record(reading);
+        first = false;
+
+        match &keys {
+            Some(keys) => {
+                if !handle_keys(keys, interval, &mut order) {
+                    return Ok(());
+                }
+            }
+            None => std::thread::sleep(interval),
+        }
+    }
+}
+
+/// Run the --on-growth command in the background. Failing to start it only warns, so
+/// the watch carries on.
+fn run_on_growth(command: &str, pid: Option<usize>, rss: usize, delta: i64) {
+    let child = on_growth_command(command, pid, rss, delta).spawn();
+
+    match child {
+        // Reap it once it's done without holding up the next reading.
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => eprintln!("Failed to run --on-growth command: {}", err),
+    }
+}
+
+/// The --on-growth command run by the shell, told what grew through its environment.
+fn on_growth_command(
+    command: &str,
+    pid: Option<usize>,
+    rss: usize,
+    delta: i64,
+) -> std::process::Command {
+    let mut shell = std::process::Command::new("sh");
+    shell
+        .arg("-c")
+        .arg(command)
+        .env(
+            "SHMAPS_PID",
+            pid.map(|pid| pid.to_string()).unwrap_or_default(),
+        )
+        .env("SHMAPS_RSS_KB", rss.to_string())
+        .env("SHMAPS_RSS_DELTA_KB", delta.to_string());
+    shell
+}
+
+/// Respond to key presses until `interval` is up, returning false if asked to quit.
+fn handle_keys(keys: &keys::Keys, interval: Duration, order: &mut Order) -> bool {
+    let deadline = Instant::now() + interval;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match keys.wait(remaining) {
+            Some(b'q') => return false,
+            Some(b'r') => {
+                order.reverse = !order.reverse;
+                eprintln!("Reversed: {}", order.reverse);
+            }
+            Some(b's') => {
+                order.next_sort();
+                match order.sort {
+                    Some(sort) => eprintln!("Sorting by {}", sort),
+                    None => eprintln!("Sorting by address"),
+                }
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    true
+}
+
+/// Like `watch`, but only follows the total RSS from statm, which is far cheaper to read.
+fn watch_rss(args: &Args, pid: usize, interval: Duration, only_changes: bool) -> error::Result<()> {
+    let mut last_rss = None;
+    loop {
+        let rss = rss(args, pid)? / 1024;
+
+        let units = args.units();
+        match last_rss {
+            Some(last_rss) if rss != last_rss => println!(
+                "{} - {} ({})",
+                chrono::Local::now(),
+                units.format(rss),
+                units.format_delta(rss as i64 - last_rss as i64)
+            ),
+            Some(_) if only_changes => {}
+            _ => println!("{} - {}", chrono::Local::now(), units.format(rss)),
+        }
+
+        last_rss = Some(rss);
+
+        std::thread::sleep(interval);
+    }
+}
+
+/// The totals of each capture in the directory, or why it couldn't be parsed.
+fn scan_dir(args: &Args, dir: &Path) -> error::Result<()> {
+    let mut paths = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|err| ShmapsError::io("list", dir, err))?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    let mut results = Vec::new();
+    let mut errors = 0;
+    for path in paths {
+        let parsed = read_contents(&path, args.mmap)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                parse::parse_all(contents.as_str(), args.strict).map_err(|err| err.to_string())
+            });
+
+        match parsed {
+            Ok(parsed) => {
+                if args.validate && !parsed.warnings.is_empty() {
+                    eprintln!("{}:", path.display());
+                    report_warnings(args, &parsed.warnings);
+                }
+                let memory_map = filter_maps(args, parsed.maps.into_iter());
+                results.push((path, Ok(totals::totals(&memory_map.collect::<Vec<_>>()))));
+            }
+            Err(err) => {
+                errors += 1;
+                results.push((path, Err(err)));
+                if args.max_parse_errors.is_some_and(|max| errors > max) {
+                    break;
+                }
+            }
+        }
+    }
+
+    let units = args.units();
+    println!(
+        "{:>5} {:>14} {:>14} {:>14}  FILE",
+        "MAPS", "RSS", "PSS", "USS"
+    );
+    for (path, totals) in &results {
+        match totals {
+            Ok(totals) => println!(
+                "{:>5} {:>14} {:>14} {:>14}  {}",
+                totals.maps,
+                units.format(totals.rss),
+                units.format(totals.pss),
+                units.format(totals.uss),
+                path.display()
+            ),
+            Err(err) => println!("{:>50}  {}: {}", "failed", path.display(), err),
+        }
+    }
+    let files = |count: usize| match count {
+        1 => "1 file".to_string(),
+        count => format!("{} files", count),
+    };
+    println!("{}, {} failed to parse", files(results.len()), errors);
+
+    if let Some(max) = args.max_parse_errors.filter(|max| errors > *max) {
+        return Err(ShmapsError::parse(
+            dir.display().to_string(),
+            format!(
+                "gave up after {} failed, more than --max-parse-errors {}",
+                files(errors),
+                max
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The totals of every process we can read the smaps of, biggest Rss first.
+fn scan_all(args: &Args) -> error::Result<()> {
+    print_processes(&mut all_processes(args)?, args.units());
+    Ok(())
+}
+
+/// The PID, command and totals of each process under the proc root with at least
+/// --min-rss, in PID order.
+fn all_processes(args: &Args) -> error::Result<Vec<(usize, String, totals::Totals)>> {
+    let pids = process::pids(&args.proc_root)
+        .map_err(|err| ShmapsError::io("list", &args.proc_root, err))?;
+
+    let mut processes = Vec::new();
+    for pid in pids {
+        // Processes come and go and most of them belong to other users, so anything we
+        // can't read is skipped.
+        let Ok(contents) = fs::read_to_string(process::proc_path(&args.proc_root, pid, "smaps"))
+        else {
+            continue;
+        };
+        let Ok(parsed) = parse::parse_all(&contents, false) else {
+            continue;
+        };
+        if args.validate && !parsed.warnings.is_empty() {
+            eprintln!("PID {}:", pid);
+            report_warnings(args, &parsed.warnings);
+        }
+
+        let maps = parsed
+            .maps
+            .iter()
+            .filter(|m| !args.deleted || m.deleted)
+            .collect::<Vec<_>>();
+        if args.deleted && maps.is_empty() {
+            continue;
+        }
+
+        let totals = totals::totals(maps);
+        if totals.rss >= args.min_rss {
+            let comm = process::comm(&args.proc_root, pid).unwrap_or_default();
+            processes.push((pid, comm, totals));
+        }
+    }
+    Ok(processes)
+}
+
+/// List the processes by Rss, biggest first.
+fn print_processes(processes: &mut [(usize, String, totals::Totals)], units: Units) {
+    processes.sort_by_key(|(_, _, totals)| std::cmp::Reverse(totals.rss));
+
+    println!(
+        "{:>8} {:<16} {:>14} {:>14} {:>14}",
+        "PID", "COMMAND", "RSS", "PSS", "USS"
+    );
+    for (pid, comm, totals) in processes {
+        println!(
+            "{:>8} {:<16} {:>14} {:>14} {:>14}",
+            pid,
+            comm,
+            units.format(totals.rss),
+            units.format(totals.pss),
+            units.format(totals.uss)
+        );
+    }
+}
+
+/// Total up each process in the cgroup, then all of them together.
+fn scan_cgroup(args: &Args, cgroup: &Path) -> error::Result<()> {
+    let procs = cgroup.join("cgroup.procs");
+    let pids = process::cgroup_pids(cgroup).map_err(|err| ShmapsError::read(&procs, err))?;
+
+    let mut processes = Vec::new();
+    let mut all = totals::Totals::default();
+    for pid in pids {
+        let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+        let memory_map = match read_memory_map(args, &smaps_path) {
+            Ok(memory_map) => memory_map,
+            Err(_) if !process::exists(&args.proc_root, pid) => continue,
+            Err(err) => return Err(err),
+        };
+        for m in &memory_map {
+            all.add(m);
+        }
+        let comm = process::comm(&args.proc_root, pid).unwrap_or_default();
+        processes.push((pid, comm, totals::totals(&memory_map)));
+    }
+
+    let units = args.units();
+    print_processes(&mut processes, units);
+    println!(
+        "{:>8} {:<16} {:>14} {:>14} {:>14}",
+        "",
+        "TOTAL",
+        units.format(all.rss),
+        units.format(all.pss),
+        units.format(all.uss)
+    );
+    if args.summary {
+        println!("{}", all.format(units));
+    }
+    Ok(())
+}
+
+fn run_top(
+    args: &Args,
+    interval: Duration,
+    rows: usize,
+    sort: TopSort,
+    count: Option<usize>,
+) -> error::Result<()> {
+    let clear = io::stdout().is_terminal();
+    for taken in 1.. {
+        let mut usages = top::read_all(&args.proc_root)
+            .map_err(|err| ShmapsError::io("list", &args.proc_root, err))?;
+        usages.sort_by_key(|usage| std::cmp::Reverse(sort.value(usage)));
+
+        if clear {
+            print!("\x1b[H\x1b[2J");
+        } else if taken > 1 {
+            println!();
+        }
+        println!("{} - {} processes", chrono::Local::now(), usages.len());
+        usages.truncate(rows);
+        print!("{}", top::format(&usages, args.units()));
+
+        if count.is_some_and(|count| taken >= count) {
+            break;
+        }
+        std::thread::sleep(interval);
+    }
+    Ok(())
+}
+
+/// The maps of every --pid together, for --aggregate.
+fn aggregated(args: &Args) -> Vec<MemoryMap> {
+    let mut memory_map = Vec::new();
+    for &pid in &args.pids {
+        let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+        let single = Args {
+            pids: vec![pid],
+            ..args.clone()
+        };
+        memory_map.extend(
+            read_memory_map(&single, &smaps_path).unwrap_or_else(|err| exit_with(&single, err)),
+        );
+    }
+    memory_map
+}
+
+/// Show a reading of several processes, one after another or with --aggregate as one.
+fn show_pids(args: &Args) -> error::Result<()> {
+    if args.rss_only {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--rss-only takes a single --pid",
+            )
+            .exit();
+    }
+
+    if args.aggregate {
+        // Nothing that checks the maps against a single process applies to them all.
+        return show(
+            &Args {
+                pids: Vec::new(),
+                verify: false,
+                ..args.clone()
+            },
+            aggregated(args),
+        );
+    }
+
+    for &pid in &args.pids {
+        let single = Args {
+            pids: vec![pid],
+            show_process: true,
+            ..args.clone()
+        };
+        let smaps_path = process::proc_path(&args.proc_root, pid, "smaps");
+        let memory_map =
+            read_memory_map(&single, &smaps_path).unwrap_or_else(|err| exit_with(&single, err));
+        show(&single, memory_map)?;
+    }
+    Ok(())
+}
+
+/// Who the process is, if --show-process was given and it can be read.
+fn process_info(args: &Args) -> Option<process::ProcessInfo> {
+    let pid = args.pid().filter(|_| args.show_process)?;
+    match read_process_info(args, pid) {
+        Ok(info) => Some(info),
+        Err(err) => {
+            eprintln!("Couldn't read the details of process {}: {}", pid, err);
+            None
+        }
+    }
+}
+
+/// A failure to write to standard output, such as the other end of a pipe closing.
+fn stdout_error(err: io::Error) -> ShmapsError {
+    ShmapsError::io("write", Path::new("standard output"), err)
+}
+
+/// Print a single reading of the memory map in the requested format.
+fn show(args: &Args, mut memory_map: Vec<MemoryMap>) -> error::Result<()> {
+    let units = args.units();
+    // Only the formats meant for people get the header, the rest are read by tools.
+    if matches!(args.format, Format::Debug | Format::Pmap) {
+        if let Some(info) = process_info(args) {
+            println!("{}", info);
+        }
+    }
+    let totals = totals::totals(&memory_map);
+
+    let order = Order {
+        sort: args.sort.or(args.top.map(|_| SortKey("Rss"))),
+        reverse: args.reverse,
+    };
+    order.apply(&mut memory_map, |m| m);
+    let rest = match args.top {
+        Some(top) if !args.files => memory_map.split_off(top.min(memory_map.len())),
+        _ => Vec::new(),
+    };
+
+    let out = || io::stdout().lock();
+    if args.segment_counts {
+        report::segment_counts(out(), &memory_map).map_err(stdout_error)?;
+    } else if args.swap_report {
+        report::swap(out(), &memory_map, units).map_err(stdout_error)?;
+    } else if let (true, Some(share_count)) = (args.pss_adjust, args.share_count) {
+        report::pss_adjust(out(), &memory_map, share_count as usize, units)
+            .map_err(stdout_error)?;
+    } else if args.gaps {
+        report::gaps(out(), &memory_map, units).map_err(stdout_error)?;
+    } else if args.by_category {
+        report::by_category(out(), &memory_map, units).map_err(stdout_error)?;
+    } else if args.by_kind {
+        report::by_kind(out(), &memory_map, units).map_err(stdout_error)?;
+    } else if args.by_inode {
+        report::by_inode(out(), &memory_map, units).map_err(stdout_error)?;
+    } else if args.files {
+        let sort = args.sort.unwrap_or(SortKey("Rss"));
+        report::files(
+            out(),
+            &memory_map,
+            |m| sort.value(m),
+            args.reverse,
+            args.top,
+            units,
+        )
+        .map_err(stdout_error)?;
+    } else if args.anon_total {
+        println!("{}", units.format(totals.anonymous));
+    } else {
+        match args.format {
+            Format::Pmap => {
+                let mut total = 0;
+                for m in memory_map {
+                    println!("{}", m.to_pmap_line());
+                    total += m.pmap_size();
+                }
+                if !rest.is_empty() {
+                    let size = rest.iter().map(MemoryMap::pmap_size).sum::<usize>();
+                    println!(
+                        "{:>16} {:>7}K ----- [ {} other maps ]",
+                        "",
+                        size,
+                        rest.len()
+                    );
+                    total += size;
+                }
+                println!(" total {:>16}K", total);
+            }
+            Format::Folded => report::folded(out(), &memory_map, |m| args.weight.value(m), units)
+                .map_err(stdout_error)?,
+            Format::Smaps => {
+                for m in memory_map {
+                    print!("{}", m.to_smaps());
+                }
+            }
+            #[cfg(feature = "serde")]
+            Format::Msgpack => {
+                snapshot::write(io::stdout().lock(), &memory_map, SnapshotFormat::Msgpack)
+                    .map_err(stdout_error)?
+            }
+            #[cfg(feature = "serde")]
+            Format::Json if args.fields.is_some() => {
+                let fields = args.fields.as_ref().unwrap();
+                let rows = memory_map
+                    .iter()
+                    .map(|m| fields.row(m, units))
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::to_string_pretty(&rows).unwrap());
+            }
+            #[cfg(feature = "serde")]
+            Format::Json => println!("{}", json_array(&memory_map, units)),
+            #[cfg(feature = "serde")]
+            Format::Jsonl if args.fields.is_some() => {
+                let fields = args.fields.as_ref().unwrap();
+                for m in &memory_map {
+                    println!("{}", serde_json::to_string(&fields.row(m, units)).unwrap());
+                }
+            }
+            #[cfg(feature = "serde")]
+            Format::Jsonl => {
+                for m in &memory_map {
+                    println!("{}", serde_json::to_string(&units.convert(m)).unwrap());
+                }
+            }
+            Format::Csv if args.fields.is_some() => csv::write_fields(
+                io::stdout().lock(),
+                &memory_map,
+                args.fields.as_ref().unwrap(),
+                units,
+                args.delimiter,
+                !args.no_header,
+            )
+            .map_err(stdout_error)?,
+            Format::Csv => csv::write(
+                io::stdout().lock(),
+                &memory_map,
+                units,
+                args.delimiter,
+                !args.no_header,
+            )
+            .map_err(stdout_error)?,
+            Format::Debug if args.fields.is_some() => {
+                memory_map.extend(totals::rest(&rest));
+                report::fields(out(), &memory_map, args.fields.as_ref().unwrap(), units)
+                    .map_err(stdout_error)?;
+            }
+            Format::Debug => {
+                // Once sorted, each map's share of the Pss shows where most of it goes.
+                let mut shares = order.sort.map(|_| totals::Shares::new(totals.pss));
+                if args.compact {
+                    for m in &memory_map {
+                        match &mut shares {
+                            Some(shares) => println!(
+                                "{} {}",
+                                shares.next(m.pss().unwrap_or_default()),
+                                m.compact_line(units)
+                            ),
+                            None => println!("{}", m.compact_line(units)),
+                        }
+                    }
+                    print_rest(&rest, shares.as_mut(), units);
+                } else {
+                    // The rest are shown as one more map, so they're printed the same way.
+                    memory_map.extend(totals::rest(&rest));
+                    for m in &memory_map {
+                        let m = units.convert(m);
+                        if let Some(template) = &args.template {
+                            println!("{}", template.render(&m));
+                        } else {
+                            println!("{:?}", m)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if args.summary {
+        println!("{}", totals.format(units));
+
+        // Filtered maps wouldn't add up to the whole process.
+        if let (Some(pid), false) = (args.pid(), args.filtered()) {
+            check_statm(args, pid, &totals);
+        }
+    }
+
+    if let (true, Some(pid)) = (args.verify, args.pid()) {
+        verify_rollup(args, pid, &totals)?;
+    }
+    Ok(())
+}
+
+/// The maps as a pretty printed JSON array, for --format json.
+#[cfg(feature = "serde")]
+fn json_array(memory_map: &[MemoryMap], units: Units) -> String {
+    let memory_map = memory_map
+        .iter()
+        .map(|m| units.convert(m))
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&memory_map).unwrap()
+}
+
+/// The line summing up the maps --top left out, if there are any, after their share of
+/// the Pss if the maps before it had theirs.
+fn print_rest(rest: &[MemoryMap], shares: Option<&mut totals::Shares>, units: Units) {
+    if rest.is_empty() {
+        return;
+    }
+    let rest = totals::totals(rest);
+    if let Some(shares) = shares {
+        print!("{} ", shares.next(rest.pss));
+    }
+    println!(
+        "{:<33}      size={:>11} rss={:>11} pss={:>11} swap={:>11}",
+        format!("{} other maps", rest.maps),
+        units.format(rest.size),
+        units.format(rest.rss),
+        units.format(rest.pss),
+        units.format(rest.swap)
+    );
+}
+
+/// Warn about any totals that smaps_rollup doesn't agree with.
+fn verify_rollup(args: &Args, pid: usize, totals: &totals::Totals) -> error::Result<()> {
+    let path = process::proc_path(&args.proc_root, pid, "smaps_rollup");
+    let contents = read_file(args, &path)?;
+    let rollup = parse::parse_rollup(contents.as_str())
+        .map_err(|err| ShmapsError::parse("smaps_rollup", err))?;
+
+    let discrepancies = totals::compare_totals(&totals::totals([&rollup]), totals);
+    if discrepancies.is_empty() {
+        eprintln!("smaps_rollup agrees with smaps");
+    }
+    for discrepancy in discrepancies {
+        eprintln!("Warning: {}", discrepancy);
+    }
+    Ok(())
+}
+
+/// Print the resident size the kernel gives in statm next to the smaps total, warning if
+/// they differ by more than a couple of pages, which suggests a torn reading.
+fn check_statm(args: &Args, pid: usize, totals: &totals::Totals) {
+    let statm = match process::read_statm(&args.proc_root, pid) {
+        Ok(statm) => statm,
+        Err(err) => {
+            eprintln!("Couldn't read statm to check the Rss: {}", err);
+            return;
+        }
+    };
+
+    let units = args.units();
+    let statm_rss = statm.rss() / 1024;
+    println!("Statm Rss:  {}", units.format(statm_rss));
+
+    if rss_differs(totals.rss, statm_rss) {
+        eprintln!(
+            "Warning: smaps Rss {} and statm Rss {} differ by more than {}",
+            units.format(totals.rss),
+            units.format(statm_rss),
+            units.format(statm_tolerance())
+        );
+    }
+}
+
+/// How far in kB the Rss from smaps and statm can drift apart between reading one and
+/// the other before it's a sign of a torn capture.
+fn statm_tolerance() -> usize {
+    2 * process::page_size() / 1024
+}
+
+fn rss_differs(smaps_rss: usize, statm_rss: usize) -> bool {
+    smaps_rss.abs_diff(statm_rss) > statm_tolerance()
+}
+
+/// The processes going by --name, of which there must only be one unless
+/// --all-matches was given.
+fn pids_named(args: &Args, name: &str) -> error::Result<Vec<usize>> {
+    let pids = process::pids_named(&args.proc_root, name)
+        .map_err(|err| ShmapsError::io("list", &args.proc_root, err))?;
+    match pids.len() {
+        0 => Err(ShmapsError::NoProcessNamed {
+            name: name.to_string(),
+        }),
+        1 => Ok(pids),
+        _ if args.all_matches => Ok(pids),
+        _ => Err(ShmapsError::AmbiguousName {
+            name: name.to_string(),
+            pids,
+        }),
+    }
+}
+
+/// The processes in the --container, of which there must be just one.
+fn container_pids(container: &str) -> error::Result<Vec<usize>> {
+    let cgroup_root = Path::new(container::CGROUP_ROOT);
+    let mut cgroups = container::find(
+        cgroup_root,
+        Path::new(container::DOCKER_CONTAINERS),
+        container,
+    )
+    .map_err(|err| ShmapsError::read(cgroup_root, err))?;
+
+    if cgroups.len() != 1 {
+        return Err(if cgroups.is_empty() {
+            ShmapsError::NoContainer {
+                container: container.to_string(),
+            }
+        } else {
+            ShmapsError::AmbiguousContainer {
+                container: container.to_string(),
+                ids: cgroups.into_keys().collect(),
+            }
+        });
+    }
+    let (_, paths) = cgroups.pop_first().unwrap();
+
+    let mut pids = Vec::new();
+    for path in paths {
+        pids.extend(
+            process::cgroup_pids(&path)
+                .map_err(|err| ShmapsError::read(&path.join("cgroup.procs"), err))?,
+        );
+    }
+    pids.sort_unstable();
+    pids.dedup();
+    Ok(pids)
+}
+
+fn main() {
+    let mut args = Args::parse();
+    if let Some(name) = &args.name {
+        match pids_named(&args, name) {
+            Ok(pids) => args.pids = pids,
+            Err(err) => exit_with(&args, err),
+        }
+    }
+    if let Some(container) = &args.container {
+        match container_pids(container) {
+            Ok(pids) => {
+                args.pids = pids;
+                args.aggregate = true;
+            }
+            Err(err) => exit_with(&args, err),
+        }
+    }
+    // Only watching looks for new children as it goes.
+    if args.tree && !args.watching() {
+        match process::tree(&args.proc_root, &args.pids) {
+            Ok(pids) => args.pids = pids,
+            Err(err) => exit_with(&args, ShmapsError::io("list", &args.proc_root, err)),
+        }
+    }
+    if let Err(err) = run(&args) {
+        exit_with(&args, err);
+    }
+}
+
+fn run(args: &Args) -> error::Result<()> {
+    if args.json() {
+        #[cfg(feature = "serde")]
+        if let Some(granularity) = args.units().granularity() {
+            eprintln!("{}", serde_json::to_string(&granularity).unwrap());
+        }
+    } else if let Some(note) = args.units().note() {
+        eprintln!("Note: {}", note);
+    }
+    match &args.command {
+        #[cfg(feature = "serde")]
+        Some(Command::Record {
+            pid,
+            period,
+            out,
+            count,
+        }) => return record(args, *pid, Duration::from_secs(*period), out, *count),
+        #[cfg(feature = "serde")]
+        Some(Command::Snapshot { pid, out }) => return save_snapshot(args, *pid, out),
+        Some(Command::Report { pid, out }) => return write_report(args, *pid, out),
+        Some(Command::Top {
+            period,
+            rows,
+            sort,
+            count,
+        }) => return run_top(args, Duration::from_secs(*period), *rows, *sort, *count),
+        Some(Command::Tui { pid, period }) => {
+            let smaps_path = process::proc_path(&args.proc_root, *pid, "smaps");
+            let title = match read_process_info(args, *pid) {
+                Ok(info) => info.to_string(),
+                Err(_) => format!("PID {}", pid),
+            };
+            return tui::run(&title, Duration::from_secs(*period), args.units(), || {
+                read_memory_map(args, &smaps_path)
+            });
+        }
+        Some(Command::Diff { old, new }) => {
+            let heading = format!("{} -> {}", old.display(), new.display());
+            print_replayed(
+                args,
+                Some(&heading),
+                &load_maps(args, old)?,
+                &load_maps(args, new)?,
+            );
+            return Ok(());
+        }
+        Some(Command::Compare { pids }) => {
+            let [old, new] = pids[..] else {
+                Args::command()
+                    .error(
+                        clap::error::ErrorKind::WrongNumberOfValues,
+                        "compare takes exactly two --pid",
+                    )
+                    .exit();
+            };
+            let describe = |pid| match read_process_info(args, pid) {
+                Ok(info) => info.to_string(),
+                Err(_) => format!("PID {}", pid),
+            };
+            let read =
+                |pid| read_memory_map(args, &process::proc_path(&args.proc_root, pid, "smaps"));
+            let heading = format!("{} -> {}", describe(old), describe(new));
+            print_headed_diffs(
+                args,
+                Some(&heading),
+                &diff::diff_by_path(&read(old)?, &read(new)?, &args.diff_config()),
+            );
+            return Ok(());
+        }
+        #[cfg(feature = "serde")]
+        Some(Command::Replay { file, speed }) => return replay(args, file, *speed),
+        None => {}
+    }
+
+    if args.all {
+        return scan_all(args);
+    }
+    if args.pids.len() > 1 && (args.rss_only || !args.watching()) {
+        return show_pids(args);
+    }
+    if let Some(dir) = &args.dir {
+        return scan_dir(args, dir);
+    }
+    if let Some(cgroup) = &args.cgroup {
+        return scan_cgroup(args, cgroup);
+    }
+
+    let smaps_path = match &args.file {
+        Some(file) => file.clone(),
+        None => process::proc_path(
+            &args.proc_root,
+            args.pid().unwrap(),
+            if args.maps { "maps" } else { "smaps" },
+        ),
+    };
+
+    if let Some((interval, only_changes)) = args.watch_interval() {
+        if args.file.as_deref().is_some_and(is_stdin) {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "standard input can only be read once, so it can't be watched",
+                )
+                .exit();
+        }
+        match (args.rss_only, args.pid()) {
+            (true, Some(pid)) => watch_rss(args, pid, interval, only_changes),
+            _ => watch(args, &smaps_path, interval, only_changes),
+        }
+    } else if let Some(delimiter) = &args.snapshot_delimiter {
+        let contents = read_file(args, &smaps_path)?;
+        replay_snapshots(args, contents.as_str(), delimiter)
+    } else if let (true, Some(pid)) = (args.rss_only, args.pid()) {
+        println!("{}", args.units().format(rss(args, pid)? / 1024));
+        Ok(())
+    } else if let Some(memory_map) = load_snapshot(args)? {
+        show(args, memory_map)
+    } else {
+        let contents = read_file(args, &smaps_path)?;
+        #[cfg(feature = "serde")]
+        if args.streamable() {
+            // Written as they're parsed so the whole map is never held in memory.
+            let mut error = None;
+            for m in parsed_maps(args, contents.as_str(), &mut error) {
+                println!(
+                    "{}",
+                    serde_json::to_string(&args.units().convert(&m)).unwrap()
+                );
+            }
+            return error.map_or(Ok(()), Err);
+        }
+        show(args, memory_maps(args, contents.as_str())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// The ranges of the `maps` left after the filters in `flags`.
+    fn kept(flags: &[&str], maps: &[&str]) -> Vec<String> {
+        let args = Args::parse_from(["shmaps", "--pid", "1"].iter().chain(flags));
+        let maps = maps
+            .iter()
+            .map(|m| m.parse::<MemoryMap>().unwrap())
+            .collect::<Vec<_>>();
+        filter_maps(&args, maps.into_iter())
+            .map(|m| m.address_range.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_watch_interval() {
+        let interval = |flags: &[&str]| {
+            Args::parse_from(["shmaps", "--pid", "1"].iter().chain(flags)).watch_interval()
+        };
+        assert_eq!(None, interval(&[]));
+        assert_eq!(
+            Some((Duration::from_secs(2), false)),
+            interval(&["--period", "2"])
+        );
+        assert_eq!(
+            Some((Duration::from_millis(250), true)),
+            interval(&["--every-change", "--max-rate", "4"])
+        );
+        assert_eq!(
+            Some((Duration::from_millis(1000 / MAX_POLL_RATE), true)),
+            interval(&["--every-change", "--max-rate", "100000"])
+        );
+    }
+
+    #[test]
+    fn test_diff_on() {
+        let config = |flags: &[&str]| {
+            Args::parse_from(["shmaps", "--pid", "1"].iter().chain(flags)).diff_config()
+        };
+        assert_eq!(diff::DiffConfig::default(), config(&[]));
+        let pss = config(&["--diff-on", "pss,permissions"]);
+        assert_eq!((vec!["Pss"], true), (pss.fields, pss.permissions));
+        assert!(Args::try_parse_from(["shmaps", "--pid", "1", "--diff-on", "bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_min_delta() {
+        let args = Args::parse_from(["shmaps", "--pid", "1", "--min-delta", "64K"]);
+        let diff_on = args.diff_config();
+        assert_eq!(64, diff_on.min_delta);
+
+        let map = |rss: usize| {
+            format!("1000-2000 rw-p 00000000 00:00 0\nRss: {} kB\n", rss)
+                .parse::<MemoryMap>()
+                .unwrap()
+        };
+        // A few pages coming and going isn't a change, growing by the delta is.
+        assert!(diff::diff_sorted(&[map(100)], &[map(160)], &diff_on).is_empty());
+        assert_eq!(
+            1,
+            diff::diff_sorted(&[map(100)], &[map(164)], &diff_on)
+                .changed
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_locked_only() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\nLocked: 4 kB\n",
+            "2000-3000 rw-p 00000000 00:00 0\nRss: 4 kB\nLocked: 0 kB\n",
+            "3000-4000 rw-p 00000000 00:00 0\nRss: 4 kB\nVmFlags: rd wr lo\n",
+        ];
+        assert_eq!(
+            vec!["00001000-00002000", "00003000-00004000"],
+            kept(&["--locked-only"], &maps)
+        );
+        assert_eq!(3, kept(&[], &maps).len());
+    }
+
+    #[test]
+    fn test_anon_and_file_backed() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0 [heap]\n",
+            "2000-3000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\n",
+            "3000-4000 rw-s 00000000 00:01 7 /memfd:wayland (deleted)\n",
+            "4000-5000 rw-p 00000000 00:00 0\n",
+        ];
+        assert_eq!(
+            vec!["00001000-00002000", "00004000-00005000"],
+            kept(&["--anon"], &maps)
+        );
+        assert_eq!(
+            vec!["00002000-00003000", "00003000-00004000"],
+            kept(&["--file-backed"], &maps)
+        );
+        assert!(Args::try_parse_from(["shmaps", "--pid", "1", "--anon", "--file-backed"]).is_err());
+    }
+
+    #[test]
+    fn test_vmflag() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0\nVmFlags: rd wr lo\n",
+            "2000-3000 rw-p 00000000 00:00 0\nVmFlags: rd wr ht\n",
+            "3000-4000 rw-p 00000000 00:00 0\nVmFlags: rd wr\n",
+        ];
+        assert_eq!(vec!["00001000-00002000"], kept(&["--vmflag", "lo"], &maps));
+        // Any of the flags given will do.
+        assert_eq!(
+            vec!["00001000-00002000", "00002000-00003000"],
+            kept(&["--vmflag", "lo,ht"], &maps)
+        );
+        assert!(Args::try_parse_from(["shmaps", "--pid", "1", "--vmflag", "lock"]).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0\n",
+            "2000-3000 rw-p 00000000 00:00 0\n",
+            "4000-5000 rw-p 00000000 00:00 0\n",
+        ];
+        // Partly overlapping the first map still counts.
+        assert_eq!(
+            vec!["00001000-00002000", "00002000-00003000"],
+            kept(&["--range", "1800-2800"], &maps)
+        );
+        assert!(kept(&["--range", "3000-4000"], &maps).is_empty());
+    }
+
+    #[test]
+    fn test_deleted() {
+        let maps = [
+            "1000-2000 r-xp 00000000 fe:00 12 /usr/lib/libold.so (deleted)\n",
+            "2000-3000 r-xp 00000000 fe:00 13 /usr/lib/libnew.so\n",
+        ];
+        let m = maps[0].parse::<MemoryMap>().unwrap();
+        assert!(m.deleted);
+        assert_eq!(Some("/usr/lib/libold.so"), m.path.as_deref());
+        assert_eq!(vec!["00001000-00002000"], kept(&["--deleted"], &maps));
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let key = SortKey::parse("private_dirty").unwrap();
+        assert_eq!(SortKey("Private_Dirty"), key);
+        let m = "1000-2000 rw-p 00000000 00:00 0\nPrivate_Dirty: 4 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        assert_eq!(4, key.value(&m));
+        assert_eq!(SortKey("Referenced"), SortKey::parse("referenced").unwrap());
+        assert!(SortKey::parse("bogus").is_err());
+    }
+
+    #[test]
+    fn test_order() {
+        let mut order = Order {
+            sort: None,
+            reverse: false,
+        };
+        order.next_sort();
+        assert_eq!(Some(SortKey("Size")), order.sort);
+        for _ in 1..SortKey::CYCLE.len() {
+            order.next_sort();
+        }
+        assert_eq!(SortKey::CYCLE.last().copied(), order.sort);
+        // Past the last field it's back to address order.
+        order.next_sort();
+        assert_eq!(None, order.sort);
+
+        let mut maps = [
+            "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n",
+            "2000-3000 rw-p 00000000 00:00 0\nRss: 8 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        order.reverse = true;
+        order.apply(&mut maps, |m| m);
+        assert_eq!(0x2000, maps[0].address_range.from);
+        order.sort = Some(SortKey("Rss"));
+        order.apply(&mut maps, |m| m);
+        assert_eq!(0x1000, maps[0].address_range.from);
+    }
+
+    #[test]
+    fn test_on_growth_command() {
+        let command = on_growth_command("notify", Some(42), 8192, 2048);
+        assert_eq!("sh", command.get_program());
+        assert_eq!(vec!["-c", "notify"], command.get_args().collect::<Vec<_>>());
+        let envs = command
+            .get_envs()
+            .map(|(name, value)| (name.to_str().unwrap(), value.unwrap().to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("SHMAPS_PID", "42"),
+                ("SHMAPS_RSS_DELTA_KB", "2048"),
+                ("SHMAPS_RSS_KB", "8192"),
+            ],
+            envs
+        );
+
+        let status = on_growth_command(r#"test "$SHMAPS_PID" = """#, None, 0, 0)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_weight() {
+        let m = "1000-2000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\nRss: 8 kB\nPss: 2 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        let weight = |flags: &[&str]| {
+            Args::parse_from(
+                ["shmaps", "--pid", "1", "--format", "folded"]
+                    .iter()
+                    .chain(flags),
+            )
+            .weight
+            .value(&m)
+        };
+        // Pss, so a library shared by many processes isn't counted in full by each.
+        assert_eq!(2, weight(&[]));
+        assert_eq!(8, weight(&["--weight", "rss"]));
+    }
+
+    #[test]
+    fn test_readings() {
+        let reading = |rss: usize| {
+            let m = format!("1000-2000 rw-p 00000000 00:00 0\nRss: {} kB\n", rss)
+                .parse::<MemoryMap>()
+                .unwrap();
+            BTreeMap::from([(1, vec![m])])
+        };
+        let baseline = |flags: &[&str]| {
+            Args::parse_from(
+                ["shmaps", "--pid", "1", "--period", "1"]
+                    .iter()
+                    .chain(flags),
+            )
+            .baseline
+        };
+
+        let mut previous = Readings::new(baseline(&[]));
+        let mut first = Readings::new(baseline(&["--baseline", "first"]));
+        for rss in [4, 8, 12] {
+            previous.record(reading(rss));
+            first.record(reading(rss));
+        }
+        assert_eq!((reading(12), None), (previous.last, previous.first));
+        assert_eq!((reading(12), Some(reading(4))), (first.last, first.first));
+    }
+
+    #[test]
+    fn test_machine_readable() {
+        let args = |format| Args::parse_from(["shmaps", "--pid", "1", "--format", format]);
+        assert!(!args("pmap").machine_readable());
+        assert!(args("csv").machine_readable());
+        assert!(args("folded").machine_readable());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_streamable() {
+        let args = |flags: &[&str]| Args::parse_from(["shmaps", "--pid", "1"].iter().chain(flags));
+        assert!(args(&["--format", "jsonl"]).streamable());
+        assert!(args(&["--format", "jsonl"]).jsonl());
+        // A plain JSON array is written whole.
+        assert!(!args(&["--format", "json"]).streamable());
+        assert!(args(&["--format", "json"]).json());
+        // Sorting needs every map first.
+        assert!(!args(&["--format", "jsonl", "--sort", "rss"]).streamable());
+        assert!(!args(&["--format", "jsonl", "--summary"]).streamable());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_write_snapshot() {
+        let dir = Scratch::new("watch-output");
+        let maps = vec!["1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap()];
+        let first = write_snapshot(dir.path(), Some(42), &maps, SnapshotFormat::Json).unwrap();
+        let second = write_snapshot(dir.path(), Some(42), &maps, SnapshotFormat::Json).unwrap();
+        assert_ne!(first, second);
+
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("smaps-42-") && name.ends_with(".json"));
+        assert_eq!(Some(maps), snapshot::load(&first).unwrap());
+    }
+
+    #[test]
+    fn test_append_csv() {
+        let dir = Scratch::new("log-csv");
+        let path = dir.join("rss.csv");
+        let totals = totals::Totals {
+            maps: 3,
+            rss: 12,
+            pss: 8,
+            swap: 4,
+            ..Default::default()
+        };
+        append_csv(&path, &totals).unwrap();
+        append_csv(&path, &totals).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(3, lines.len());
+        assert_eq!("timestamp,rss_kb,pss_kb,swap_kb,map_count", lines[0]);
+        for row in &lines[1..] {
+            let (timestamp, figures) = row.split_once(',').unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+            assert_eq!("12,8,4,3", figures);
+        }
+    }
+
+    #[test]
+    fn test_rss_differs() {
+        let page = process::page_size() / 1024;
+        assert!(!rss_differs(100 * page, 100 * page));
+        // A page or two either way is just timing.
+        assert!(!rss_differs(100 * page, 102 * page));
+        assert!(!rss_differs(102 * page, 100 * page));
+        assert!(rss_differs(100 * page, 103 * page));
+        assert!(rss_differs(103 * page, 100 * page));
+    }
+
+    #[test]
+    fn test_all_processes() {
+        let root = Scratch::new("all");
+        for (pid, rss) in [(1, 400), (2, 40)] {
+            fs::create_dir(root.join(pid.to_string())).unwrap();
+            fs::write(
+                root.join(format!("{}/smaps", pid)),
+                format!("1000-2000 rw-p 00000000 00:00 0\nRss: {} kB\n", rss),
+            )
+            .unwrap();
+            fs::write(root.join(format!("{}/comm", pid)), format!("proc{}\n", pid)).unwrap();
+        }
+        // One that's gone or isn't ours to read, and a directory that isn't a process.
+        fs::create_dir(root.join("3")).unwrap();
+        fs::create_dir(root.join("sys")).unwrap();
+
+        let processes = |min_rss: &str| {
+            let proc_root = root.path().to_str().unwrap();
+            let args = Args::parse_from([
+                "shmaps",
+                "--all",
+                "--proc-root",
+                proc_root,
+                "--min-rss",
+                min_rss,
+            ]);
+            all_processes(&args)
+                .unwrap()
+                .into_iter()
+                .map(|(pid, comm, totals)| (pid, comm, totals.rss))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![(1, "proc1".to_string(), 400), (2, "proc2".to_string(), 40)],
+            processes("0")
+        );
+        assert_eq!(vec![(1, "proc1".to_string(), 400)], processes("100"));
+    }
+
+    #[test]
+    fn test_scan_dir() {
+        let dir = Scratch::new("dir");
+        fs::write(
+            dir.join("good.smaps"),
+            "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("torn.smaps"),
+            "1000-2000 rw-p 00000000 00:00 0\nRss: x kB\n",
+        )
+        .unwrap();
+
+        let scan = |flags: &[&str]| {
+            let path = dir.path().to_str().unwrap();
+            let args = Args::parse_from(["shmaps", "--dir", path].iter().chain(flags));
+            scan_dir(&args, dir.path())
+        };
+        // A bad file is listed with the rest unless there's a limit it goes over.
+        assert!(scan(&[]).is_ok());
+        assert!(scan(&["--max-parse-errors", "1"]).is_ok());
+        assert!(scan(&["--max-parse-errors", "0"]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_array() {
+        let maps = vec![
+            "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\nVmFlags: rd wr\n"
+                .parse::<MemoryMap>()
+                .unwrap(),
+        ];
+        let json =
+            serde_json::from_str::<serde_json::Value>(&json_array(&maps, Units::Kb)).unwrap();
+        assert_eq!(4096, json[0]["address_range"]["from"]);
+        assert_eq!(4, json[0]["sizes"]["Rss"]["Kb"]);
+        let bytes =
+            serde_json::from_str::<serde_json::Value>(&json_array(&maps, Units::Bytes)).unwrap();
+        assert_eq!(4096, bytes[0]["sizes"]["Rss"]["Bytes"]);
+
+        // What's written can be read back with --file.
+        let dir = Scratch::new("json");
+        let path = dir.join("maps.json");
+        fs::write(&path, json_array(&maps, Units::Kb)).unwrap();
+        let args = Args::parse_from(["shmaps", "--file", path.to_str().unwrap()]);
+        assert_eq!(maps, load_maps(&args, &path).unwrap());
+    }
+
+    #[test]
+    fn test_diff_files() {
+        let dir = Scratch::new("diff");
+        let (old, new) = (dir.join("before.smaps"), dir.join("after.smaps"));
+        fs::write(&old, "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n").unwrap();
+        fs::write(&new, "1000-2000 rw-p 00000000 00:00 0\nRss: 8 kB\n3000-4000 rw-p 00000000 00:00 0\nRss: 4 kB\n").unwrap();
+
+        let args = Args::parse_from([
+            "shmaps",
+            "diff",
+            old.to_str().unwrap(),
+            new.to_str().unwrap(),
+        ]);
+        let Some(Command::Diff { old, new }) = &args.command else {
+            panic!("expected diff, got {:?}", args.command);
+        };
+        let diffs = diff::diff_sorted(
+            &load_maps(&args, old).unwrap(),
+            &load_maps(&args, new).unwrap(),
+            &args.diff_config(),
+        );
+        assert_eq!(
+            (1, 0, 1),
+            (diffs.added.len(), diffs.removed.len(), diffs.changed.len())
+        );
+    }
+
+    #[test]
+    fn test_read_contents() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("./-")));
+
+        let dir = Scratch::new("capture");
+        let path = dir.join("smaps-dump");
+        let capture = "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n";
+        fs::write(&path, capture).unwrap();
+        // Read or mapped, a capture on disk gives the same text.
+        for use_mmap in [false, true] {
+            assert_eq!(capture, read_contents(&path, use_mmap).unwrap().as_str());
+        }
+        fs::write(&path, b"\xff\xfe").unwrap();
+        assert!(read_contents(&path, true).is_err());
+    }
+
+    #[test]
+    fn test_aggregated() {
+        let root = Scratch::new("aggregate");
+        for pid in [1, 2, 3] {
+            fs::create_dir(root.join(pid.to_string())).unwrap();
+            fs::write(
+                root.join(format!("{}/smaps", pid)),
+                "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n",
+            )
+            .unwrap();
+        }
+
+        let proc_root = root.path().to_str().unwrap();
+        let args = Args::parse_from([
+            "shmaps",
+            "--pid",
+            "1,2",
+            "--pid",
+            "3",
+            "--aggregate",
+            "--proc-root",
+            proc_root,
+        ]);
+        assert_eq!(vec![1, 2, 3], args.pids);
+        assert_eq!(12, totals::totals(&aggregated(&args)).rss);
+    }
+
+    #[test]
+    fn test_reading_rss() {
+        let totals = totals::Totals {
+            rss: 12,
+            ..Default::default()
+        };
+        // statm isn't read, there being none under this root.
+        let args = Args::parse_from(["shmaps", "--pid", "1,2", "--proc-root", "/nonexistent"]);
+        assert_eq!(12, reading_rss(&args, &totals).unwrap());
+        let args = Args::parse_from([
+            "shmaps",
+            "--pid",
+            "1",
+            "--tree",
+            "--proc-root",
+            "/nonexistent",
+        ]);
+        assert_eq!(12, reading_rss(&args, &totals).unwrap());
+        let args = Args::parse_from(["shmaps", "--pid", "1", "--proc-root", "/nonexistent"]);
+        assert!(reading_rss(&args, &totals).is_err());
+    }
+
+    #[test]
+    fn test_anonymize_paths() {
+        let args = Args::parse_from(["shmaps", "--pid", "1", "--anonymize-paths"]);
+        let m = "1000-2000 r--p 00000000 fe:00 12 /home/alice/lib/libfoo.so\nRss: 4 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        let path = filter_maps(&args, [m].into_iter())
+            .next()
+            .unwrap()
+            .path
+            .unwrap();
+        assert!(!path.contains("alice"));
+        assert!(path.ends_with("/lib/libfoo.so"));
+    }
+
+    #[test]
+    fn test_limit() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0\n",
+            "2000-3000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\n",
+            "3000-4000 rw-p 00000000 00:00 0\n",
+        ];
+        assert_eq!(
+            vec!["00001000-00002000", "00002000-00003000"],
+            kept(&["--limit", "2"], &maps)
+        );
+        // The filters only see the maps inside the limit.
+        assert_eq!(
+            vec!["00001000-00002000"],
+            kept(&["--limit", "2", "--anon"], &maps)
+        );
+        assert!(Args::parse_from(["shmaps", "--pid", "1", "--limit", "2"]).filtered());
+    }
+
+    #[test]
+    fn test_relative_addresses_limit() {
+        let args = Args::parse_from([
+            "shmaps",
+            "--pid",
+            "1",
+            "--relative-addresses",
+            "--limit",
+            "1",
+        ]);
+        let m = "7000-8000 rw-p 00000000 00:00 0\nRss: 4 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        // Reading on past the limit would panic.
+        let maps = std::iter::once(m).chain(std::iter::from_fn(|| panic!("read past --limit")));
+        let ranges = filter_maps(&args, maps)
+            .map(|m| m.address_range.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["00000000-00001000"], ranges);
+    }
+
+    #[test]
+    fn test_relative_addresses_unsorted() {
+        let args = Args::parse_from(["shmaps", "--pid", "1", "--relative-addresses"]);
+        let maps = ["7000-8000", "1000-2000"].map(|range| {
+            format!("{} rw-p 00000000 00:00 0\nRss: 4 kB\n", range)
+                .parse::<MemoryMap>()
+                .unwrap()
+        });
+        let ranges = filter_maps(&args, maps.into_iter())
+            .map(|m| m.address_range.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["00000000-00001000", "00000000-00000000"], ranges);
     }
 }
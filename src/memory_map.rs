@@ -1,9 +1,134 @@
-use std::{collections::BTreeMap, fmt::Display};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::units::Units;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    str::FromStr,
+};
+
+/// Declares `SmapsFields` with a field for each kB size current kernels write, along
+/// with `SIZE_FIELDS` giving their names in the order the kernel writes them.
+macro_rules! smaps_fields {
+    ($($field:ident => $name:literal,)*) => {
+        /// The kB sizes of a map. Each is `None` where the kernel didn't write it, any
+        /// fields this doesn't know about go in `extra`.
+        #[derive(PartialEq, Eq, Debug, Clone, Default)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub struct SmapsFields {
+            $(
+                #[cfg_attr(
+                    feature = "serde",
+                    serde(rename = $name, default, skip_serializing_if = "Option::is_none")
+                )]
+                pub $field: Option<Numeric>,
+            )*
+            #[cfg_attr(feature = "serde", serde(flatten))]
+            pub extra: BTreeMap<String, Numeric>,
+        }
+
+        /// The kB size fields current kernels write for each map, in the order they appear.
+        pub const SIZE_FIELDS: &[&str] = &[$($name,)*];
+
+        impl SmapsFields {
+            /// The field by the name the kernel gives it, eg. `Pss_Dirty`.
+            pub fn get(&self, name: &str) -> Option<&Numeric> {
+                match name {
+                    $($name => self.$field.as_ref(),)*
+                    _ => self.extra.get(name),
+                }
+            }
+
+            pub fn insert(&mut self, name: String, value: Numeric) {
+                match name.as_str() {
+                    $($name => self.$field = Some(value),)*
+                    _ => {
+                        self.extra.insert(name, value);
+                    }
+                }
+            }
+
+            /// Every field that was given, in kernel order with the unknown ones after.
+            pub fn iter(&self) -> impl Iterator<Item = (&str, &Numeric)> {
+                [$(($name, self.$field.as_ref()),)*]
+                    .into_iter()
+                    .filter_map(|(name, value)| Some((name, value?)))
+                    .chain(self.extra.iter().map(|(name, value)| (name.as_str(), value)))
+            }
+
+            pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Numeric> {
+                [$(self.$field.as_mut(),)*]
+                    .into_iter()
+                    .flatten()
+                    .chain(self.extra.values_mut())
+            }
+        }
+    };
+}
+
+smaps_fields! {
+    size => "Size",
+    kernel_page_size => "KernelPageSize",
+    mmu_page_size => "MMUPageSize",
+    rss => "Rss",
+    pss => "Pss",
+    pss_dirty => "Pss_Dirty",
+    pss_anon => "Pss_Anon",
+    pss_file => "Pss_File",
+    pss_shmem => "Pss_Shmem",
+    shared_clean => "Shared_Clean",
+    shared_dirty => "Shared_Dirty",
+    private_clean => "Private_Clean",
+    private_dirty => "Private_Dirty",
+    referenced => "Referenced",
+    anonymous => "Anonymous",
+    ksm => "KSM",
+    lazy_free => "LazyFree",
+    anon_huge_pages => "AnonHugePages",
+    shmem_pmd_mapped => "ShmemPmdMapped",
+    file_pmd_mapped => "FilePmdMapped",
+    shared_hugetlb => "Shared_Hugetlb",
+    private_hugetlb => "Private_Hugetlb",
+    swap => "Swap",
+    swap_pss => "SwapPss",
+    locked => "Locked",
+}
+
+/// Match a size field name regardless of case, giving the name the kernel uses.
+pub fn size_field(value: &str) -> Result<&'static str, String> {
+    SIZE_FIELDS
+        .iter()
+        .find(|field| field.eq_ignore_ascii_case(value))
+        .copied()
+        .ok_or_else(|| format!("unknown field, expected one of {}", SIZE_FIELDS.join(", ")))
+}
+
+/// What the kernel adds to the path of a map whose file has been deleted.
+pub const DELETED_SUFFIX: &str = " (deleted)";
+
+/// The fields the kernel gives as bare numbers rather than kB.
+pub const NUMBER_FIELDS: &[&str] = &["THPeligible", "ProtectionKey"];
+
+/// The total of the sizes that were given, or `None` if none of them were.
+fn sum(sizes: &[&Option<Numeric>]) -> Option<usize> {
+    sizes
+        .iter()
+        .filter_map(|size| size.as_ref().map(Numeric::value))
+        .reduce(|total, size| total + size)
+}
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Numeric {
     Number(usize),
+    /// A size as the kernel gives it. Sizes are counted in whole pages, apart from the
+    /// Pss fields which are rounded down to the kB, so a 100 byte allocation shows as 4 kB.
     Kb(usize),
+    /// A `Kb` size converted for `--bytes`. It's no more precise than the kB it came from.
+    Bytes(usize),
 }
 
 impl Display for Numeric {
@@ -11,6 +136,7 @@ impl Display for Numeric {
         match self {
             Numeric::Number(num) => write!(f, "{}", num),
             Numeric::Kb(num) => write!(f, "{} kB", num),
+            Numeric::Bytes(num) => write!(f, "{} B", num),
         }
     }
 }
@@ -20,26 +146,400 @@ impl Numeric {
         match self {
             Numeric::Number(num) => *num,
             Numeric::Kb(num) => *num,
+            Numeric::Bytes(num) => *num,
+        }
+    }
+
+    /// Whether the size, the field `name`, is only accurate to a page. Every size is
+    /// apart from the Pss fields, which are only rounded down to the kB.
+    pub fn is_page_granular(&self, name: &str) -> bool {
+        match self {
+            Numeric::Number(_) => false,
+            Numeric::Kb(_) | Numeric::Bytes(_) => !name.starts_with("Pss") && name != "SwapPss",
+        }
+    }
+
+    /// The size in bytes, or `None` if this isn't a size.
+    pub fn as_bytes(&self) -> Option<u64> {
+        match self {
+            Numeric::Number(_) => None,
+            Numeric::Kb(num) => Some(*num as u64 * 1024),
+            Numeric::Bytes(num) => Some(*num as u64),
         }
     }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Range {
     pub from: usize,
     pub to: usize,
 }
 
+/// Pads to at least 8 digits, the same as the kernel does in `/proc/<pid>/maps`.
 impl Display for Range {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:x}-{:x}", self.from, self.to)
+        write!(f, "{:08x}-{:08x}", self.from, self.to)
+    }
+}
+
+impl Range {
+    /// Whether the address is in the range. Like the kernel, `to` is the first address
+    /// past the end so isn't included.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.from <= addr && addr < self.to
+    }
+
+    /// Whether any address is in both ranges.
+    pub fn overlaps(&self, other: &Range) -> bool {
+        self.from < other.to && other.from < self.to
+    }
+
+    /// The number of bytes in the range.
+    pub fn len(&self) -> usize {
+        self.to.saturating_sub(self.from)
+    }
+
+    /// The range as offsets from `base`, which should be at or below it.
+    pub fn relative_to(&self, base: usize) -> Range {
+        Range {
+            from: self.from.saturating_sub(base),
+            to: self.to.saturating_sub(base),
+        }
+    }
+}
+
+/// The access a map allows, as in the `rw-p` column of smaps.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "String", try_from = "String")
+)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    /// Shared with other processes (`s`) rather than private copy on write (`p`).
+    pub shared: bool,
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |set, c| if set { c } else { '-' };
+        let perms = [
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x'),
+            if self.shared { 's' } else { 'p' },
+        ];
+        f.pad(&perms.iter().collect::<String>())
+    }
+}
+
+impl Permissions {
+    /// Whether the map can't be accessed at all, eg. a guard page.
+    pub fn is_none(&self) -> bool {
+        !self.read && !self.write && !self.execute
+    }
+
+    /// Memory that can be written and then run, which W^X policies forbid.
+    pub fn is_writable_and_executable(&self) -> bool {
+        self.write && self.execute
+    }
+}
+
+impl TryFrom<&str> for Permissions {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let [read, write, execute, shared] = value.as_bytes() else {
+            return Err("permissions must be 4 characters");
+        };
+        let flag = |c: &u8, set| match c {
+            b'-' => Ok(false),
+            c if *c == set => Ok(true),
+            _ => Err("unknown permission"),
+        };
+
+        Ok(Self {
+            read: flag(read, b'r')?,
+            write: flag(write, b'w')?,
+            execute: flag(execute, b'x')?,
+            shared: match shared {
+                b's' => true,
+                b'p' => false,
+                _ => return Err("unknown permission"),
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for Permissions {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Permissions::try_from(value.as_str())
+    }
+}
+
+impl From<Permissions> for String {
+    fn from(value: Permissions) -> Self {
+        value.to_string()
+    }
+}
+
+/// Which permissions a map must have for `--perm`, each `None` where it doesn't matter.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct PermissionPattern {
+    pub read: Option<bool>,
+    pub write: Option<bool>,
+    pub execute: Option<bool>,
+    pub shared: Option<bool>,
+}
+
+impl PermissionPattern {
+    pub fn matches(&self, perms: &Permissions) -> bool {
+        [
+            (self.read, perms.read),
+            (self.write, perms.write),
+            (self.execute, perms.execute),
+            (self.shared, perms.shared),
+        ]
+        .iter()
+        .all(|(wanted, set)| wanted.is_none_or(|wanted| wanted == *set))
+    }
+}
+
+impl FromStr for PermissionPattern {
+    type Err = String;
+
+    /// Either the four columns as smaps writes them with `?` for any, eg. `rw?p` or `r-x?`,
+    /// or just the letters that must be set in any order, eg. `wx`. `p` asks for a private
+    /// map and `s` a shared one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("expected eg. rw-p, r?x? or wx, not {}", s);
+
+        if let [read, write, execute, shared] = s.as_bytes() {
+            let flag = |c: &u8, set| match c {
+                b'-' => Some(Some(false)),
+                b'?' => Some(None),
+                c if *c == set => Some(Some(true)),
+                _ => None,
+            };
+            let shared = match shared {
+                b's' => Some(Some(true)),
+                b'p' => Some(Some(false)),
+                b'?' => Some(None),
+                _ => None,
+            };
+            if let (Some(read), Some(write), Some(execute), Some(shared)) = (
+                flag(read, b'r'),
+                flag(write, b'w'),
+                flag(execute, b'x'),
+                shared,
+            ) {
+                return Ok(PermissionPattern {
+                    read,
+                    write,
+                    execute,
+                    shared,
+                });
+            }
+        }
+
+        let mut pattern = PermissionPattern::default();
+        for c in s.chars() {
+            let (flag, value) = match c {
+                'r' => (&mut pattern.read, true),
+                'w' => (&mut pattern.write, true),
+                'x' => (&mut pattern.execute, true),
+                's' => (&mut pattern.shared, true),
+                'p' => (&mut pattern.shared, false),
+                _ => return Err(error()),
+            };
+            if flag.is_some_and(|set| set != value) {
+                return Err(error());
+            }
+            *flag = Some(value);
+        }
+        match s.is_empty() {
+            true => Err(error()),
+            false => Ok(pattern),
+        }
+    }
+}
+
+/// The device a file backed map's file is on, as in the `fe:00` column of smaps.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "String", try_from = "String")
+)]
+pub struct Device {
+    pub major: u32,
+    pub minor: u32,
+}
+
+/// In hex, as the kernel writes it.
+impl Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format!("{:02x}:{:02x}", self.major, self.minor))
+    }
+}
+
+impl TryFrom<&str> for Device {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (major, minor) = value.split_once(':').ok_or("missing :")?;
+        let parse = |num| u32::from_str_radix(num, 16).map_err(|_| "cant parse");
+
+        Ok(Self {
+            major: parse(major)?,
+            minor: parse(minor)?,
+        })
+    }
+}
+
+impl TryFrom<String> for Device {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Device::try_from(value.as_str())
+    }
+}
+
+impl From<Device> for String {
+    fn from(value: Device) -> Self {
+        value.to_string()
+    }
+}
+
+/// Declares `VmFlag` with a variant for each of the two letter flags the kernel writes
+/// after `VmFlags:`.
+macro_rules! vm_flags {
+    ($($(#[$doc:meta])* $flag:ident => $mnemonic:literal,)*) => {
+        #[derive(PartialEq, Eq, Debug, Clone)]
+        pub enum VmFlag {
+            $($(#[$doc])* $flag,)*
+            /// A flag from a kernel newer than this.
+            Unknown(String),
+        }
+
+        impl VmFlag {
+            /// The two letters the kernel writes for the flag, eg. `lo`.
+            pub fn mnemonic(&self) -> &str {
+                match self {
+                    $(VmFlag::$flag => $mnemonic,)*
+                    VmFlag::Unknown(mnemonic) => mnemonic,
+                }
+            }
+        }
+
+        impl From<&str> for VmFlag {
+            fn from(value: &str) -> Self {
+                match value {
+                    $($mnemonic => VmFlag::$flag,)*
+                    _ => VmFlag::Unknown(value.to_string()),
+                }
+            }
+        }
+    };
+}
+
+vm_flags! {
+    Read => "rd",
+    Write => "wr",
+    Exec => "ex",
+    Shared => "sh",
+    MayRead => "mr",
+    MayWrite => "mw",
+    MayExec => "me",
+    MayShare => "ms",
+    /// The stack grows down into the map.
+    GrowsDown => "gd",
+    PfnMap => "pf",
+    DenyWrite => "dw",
+    Locked => "lo",
+    Io => "io",
+    SequentialRead => "sr",
+    RandomRead => "rr",
+    DontCopy => "dc",
+    DontExpand => "de",
+    LockOnFault => "lf",
+    Accountable => "ac",
+    NoReserve => "nr",
+    HugeTlb => "ht",
+    SyncFault => "sf",
+    Arch => "ar",
+    WipeOnFork => "wf",
+    DontDump => "dd",
+    SoftDirty => "sd",
+    MixedMap => "mm",
+    HugePage => "hg",
+    NoHugePage => "nh",
+    Mergeable => "mg",
+    UffdMissing => "um",
+    UffdWriteProtect => "uw",
+    UffdMinor => "ui",
+    /// Arm64 branch target identification.
+    Bti => "bt",
+    /// Arm64 memory tagging.
+    Mte => "mt",
+    ShadowStack => "ss",
+    Sealed => "sl",
+}
+
+/// The flags of a map, in the order the kernel gave them.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "String", from = "String")
+)]
+pub struct VmFlags(pub Vec<VmFlag>);
+
+impl VmFlags {
+    pub fn contains(&self, flag: &VmFlag) -> bool {
+        self.0.contains(flag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for VmFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = self.0.iter().map(VmFlag::mnemonic).collect::<Vec<_>>();
+        f.pad(&flags.join(" "))
+    }
+}
+
+impl From<&str> for VmFlags {
+    fn from(value: &str) -> Self {
+        VmFlags(value.split_whitespace().map(VmFlag::from).collect())
+    }
+}
+
+impl From<String> for VmFlags {
+    fn from(value: String) -> Self {
+        VmFlags::from(value.as_str())
+    }
+}
+
+impl From<VmFlags> for String {
+    fn from(value: VmFlags) -> Self {
+        value.to_string()
     }
 }
 
 /// Only compare the from
 impl PartialOrd for Range {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.from.partial_cmp(&other.from)
+        Some(self.cmp(other))
     }
 }
 
@@ -53,53 +553,228 @@ impl TryFrom<&str> for Range {
     type Error = &'static str;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let split = value.split("-").collect::<Vec<_>>();
+        let (from, to) = value.split_once('-').ok_or("missing -")?;
+        let parse = |num: &str| {
+            let num = num.trim_start_matches("0x");
+            usize::from_str_radix(num, 16).map_err(|_| "cant parse")
+        };
+
         Ok(Self {
-            from: usize::from_str_radix(split[0], 16).map_err(|_| "cant parse")?,
-            to: usize::from_str_radix(split[1], 16).map_err(|_| "cant parse")?,
+            from: parse(from)?,
+            to: parse(to)?,
         })
     }
 }
 
+/// A broad idea of what a map is used for, from its path and permissions.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MemoryCategory {
+    /// Executable file backed maps, the text of the binary and its libraries.
+    Code,
+    ReadOnlyData,
+    /// Writable private file backed maps, such as `.data` and `.bss`.
+    MutableData,
+    /// Maps shared with other processes.
+    Shared,
+    Heap,
+    Stack,
+    /// Private anonymous memory, mostly from the allocator.
+    Anonymous,
+    /// Anonymous memory that can be executed but not written, such as JIT output.
+    AnonymousExec,
+    /// Anonymous memory that is writable and executable at once.
+    AnonymousRwx,
+    /// Maps with no access at all, usually guard pages or reserved address space.
+    Guard,
+    /// The maps the kernel provides, such as `[vdso]`.
+    Kernel,
+}
+
+impl Display for MemoryCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MemoryCategory::Code => "code",
+            MemoryCategory::ReadOnlyData => "read only data",
+            MemoryCategory::MutableData => "mutable data",
+            MemoryCategory::Shared => "shared",
+            MemoryCategory::Heap => "heap",
+            MemoryCategory::Stack => "stack",
+            MemoryCategory::Anonymous => "anonymous",
+            MemoryCategory::AnonymousExec => "anonymous exec",
+            MemoryCategory::AnonymousRwx => "anonymous rwx",
+            MemoryCategory::Guard => "guard",
+            MemoryCategory::Kernel => "kernel",
+        };
+        f.pad(name)
+    }
+}
+
+/// What a map is backed by, from its path. Unlike `MemoryCategory` this doesn't look
+/// at the permissions.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MappingKind {
+    Heap,
+    Stack,
+    Vdso,
+    Vvar,
+    Vsyscall,
+    /// No file, including anonymous maps named with `prctl`, eg. `[anon:jemalloc]`.
+    Anonymous,
+    File,
+    /// A file from `memfd_create`, shown as `/memfd:<name>`.
+    Memfd,
+    /// System V or POSIX shared memory.
+    Shm,
+    /// Any other name the kernel puts in brackets, such as `[uprobes]`.
+    Pseudo,
+}
+
+const MAPPING_KINDS: &[(MappingKind, &str)] = &[
+    (MappingKind::Heap, "heap"),
+    (MappingKind::Stack, "stack"),
+    (MappingKind::Vdso, "vdso"),
+    (MappingKind::Vvar, "vvar"),
+    (MappingKind::Vsyscall, "vsyscall"),
+    (MappingKind::Anonymous, "anonymous"),
+    (MappingKind::File, "file"),
+    (MappingKind::Memfd, "memfd"),
+    (MappingKind::Shm, "shm"),
+    (MappingKind::Pseudo, "pseudo"),
+];
+
+impl MappingKind {
+    /// Memory that isn't backed by any file, where malloc and the stacks get theirs.
+    pub fn is_anonymous(&self) -> bool {
+        matches!(
+            self,
+            MappingKind::Heap | MappingKind::Stack | MappingKind::Anonymous
+        )
+    }
+
+    /// Maps of a file, including memfds and shared memory which live in a filesystem.
+    pub fn is_file_backed(&self) -> bool {
+        matches!(
+            self,
+            MappingKind::File | MappingKind::Memfd | MappingKind::Shm
+        )
+    }
+}
+
+impl Display for MappingKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (_, name) = MAPPING_KINDS
+            .iter()
+            .find(|(kind, _)| kind == self)
+            .expect("every kind has a name");
+        f.pad(name)
+    }
+}
+
+impl FromStr for MappingKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MAPPING_KINDS
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|(kind, _)| *kind)
+            .ok_or_else(|| {
+                let names = MAPPING_KINDS.iter().map(|(_, name)| *name);
+                format!("expected one of {}", names.collect::<Vec<_>>().join(", "))
+            })
+    }
+}
+
+/// Something about a map that should never happen in a well formed capture.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InvariantViolation {
+    RssExceedsSize { rss: usize, size: usize },
+    PssExceedsRss { pss: usize, rss: usize },
+    BreakdownExceedsRss { breakdown: usize, rss: usize },
+    SizeMismatch { size: usize, range: usize },
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvariantViolation::RssExceedsSize { rss, size } => {
+                write!(f, "Rss {} kB is more than Size {} kB", rss, size)
+            }
+            InvariantViolation::PssExceedsRss { pss, rss } => {
+                write!(f, "Pss {} kB is more than Rss {} kB", pss, rss)
+            }
+            InvariantViolation::BreakdownExceedsRss { breakdown, rss } => write!(
+                f,
+                "Private and Shared pages add up to {} kB, more than Rss {} kB",
+                breakdown, rss
+            ),
+            InvariantViolation::SizeMismatch { size, range } => {
+                write!(f, "Size {} kB doesn't match the {} kB range", size, range)
+            }
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MemoryMap {
     pub address_range: Range,
-    pub permissions: String,
-    pub offset: String,
-    pub device: String,
-    pub inode: String,
+    pub permissions: Permissions,
+    /// Where in the file the map starts, in bytes.
+    pub offset: u64,
+    pub device: Device,
+    /// 0 for maps that aren't backed by a file.
+    pub inode: u64,
+    /// Without the ` (deleted)` the kernel adds for files that have been removed.
     pub path: Option<String>,
-    pub sizes: BTreeMap<String, Numeric>,
-    pub vm_flags: String,
+    /// The file has been deleted since it was mapped, eg. a library replaced by an
+    /// upgrade.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub deleted: bool,
+    pub sizes: SmapsFields,
+    /// The fields that are plain numbers rather than kB sizes, eg. ProtectionKey.
+    pub flags: BTreeMap<String, usize>,
+    pub vm_flags: VmFlags,
+    /// The process the map belongs to, when several processes are read together and their
+    /// maps could otherwise be mistaken for each other's.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub pid: Option<usize>,
 }
 
 impl Display for MemoryMap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "{} {} {} {} {} {} {}",
-            self.address_range,
-            self.permissions,
-            self.offset,
-            self.device,
-            self.inode,
-            self.vm_flags,
-            self.path.as_ref().map(|s| s.as_str()).unwrap_or_default()
-        )?;
+        writeln!(f, "{}", self.heading())?;
 
-        for (key, val) in &self.sizes {
+        for (key, val) in self.sizes.iter() {
             if val.value() != 0 {
                 writeln!(f, "{}={}", key, val)?;
             }
         }
 
+        for (key, val) in &self.flags {
+            if *val != 0 {
+                writeln!(f, "{}={}", key, val)?;
+            }
+        }
+
         Ok(())
     }
 }
 
+/// A size that's missing counts as nothing.
+fn kb(size: &Option<Numeric>) -> usize {
+    size.as_ref().map_or(0, Numeric::value)
+}
+
 impl PartialOrd for MemoryMap {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.address_range.partial_cmp(&other.address_range)
+        Some(self.cmp(other))
     }
 }
 
@@ -111,10 +786,675 @@ impl Ord for MemoryMap {
 
 impl MemoryMap {
     pub fn size(&self) -> Option<usize> {
-        self.sizes.get("Size").map(|size| size.value())
+        self.sizes.size.as_ref().map(Numeric::value)
     }
 
     pub fn rss(&self) -> Option<usize> {
-        self.sizes.get("Rss").map(|rss| rss.value())
+        self.sizes.rss.as_ref().map(Numeric::value)
+    }
+
+    pub fn pss(&self) -> Option<usize> {
+        self.sizes.pss.as_ref().map(Numeric::value)
+    }
+
+    pub fn referenced(&self) -> Option<usize> {
+        self.sizes.referenced.as_ref().map(Numeric::value)
+    }
+
+    pub fn swap(&self) -> Option<usize> {
+        self.sizes.swap.as_ref().map(Numeric::value)
+    }
+
+    pub fn swap_pss(&self) -> Option<usize> {
+        self.sizes.swap_pss.as_ref().map(Numeric::value)
+    }
+
+    /// The unique set size, the pages only this process has mapped (Private_Clean and
+    /// Private_Dirty), which is what would be freed if it exited.
+    pub fn uss(&self) -> Option<usize> {
+        sum(&[&self.sizes.private_clean, &self.sizes.private_dirty])
+    }
+
+    /// The pages also mapped by other processes (Shared_Clean and Shared_Dirty).
+    pub fn shared(&self) -> Option<usize> {
+        sum(&[&self.sizes.shared_clean, &self.sizes.shared_dirty])
+    }
+
+    /// The anonymous pages of the map, which includes the copy on write pages of a
+    /// file backed map.
+    pub fn anonymous(&self) -> Option<usize> {
+        self.sizes.anonymous.as_ref().map(Numeric::value)
+    }
+
+    pub fn locked(&self) -> Option<usize> {
+        self.sizes.locked.as_ref().map(Numeric::value)
+    }
+
+    /// Whether any of the map is mlocked. Kernels that don't report the `Locked`
+    /// field still mark locked maps with the `lo` VmFlag.
+    pub fn is_locked(&self) -> bool {
+        self.locked().unwrap_or_default() > 0 || self.vm_flags.contains(&VmFlag::Locked)
+    }
+
+    pub fn category(&self) -> MemoryCategory {
+        let path = self.path.as_deref().unwrap_or_default();
+        let perms = self.permissions;
+
+        match path {
+            "[vdso]" | "[vvar]" | "[vvar_vclock]" | "[vsyscall]" => MemoryCategory::Kernel,
+            "[heap]" => MemoryCategory::Heap,
+            _ if path == "[stack]" || path.starts_with("[stack:") => MemoryCategory::Stack,
+            _ if perms.is_none() => MemoryCategory::Guard,
+            _ if perms.shared => MemoryCategory::Shared,
+            _ if path.starts_with('/') => {
+                if perms.execute {
+                    MemoryCategory::Code
+                } else if perms.write {
+                    MemoryCategory::MutableData
+                } else {
+                    MemoryCategory::ReadOnlyData
+                }
+            }
+            _ if perms.is_writable_and_executable() => MemoryCategory::AnonymousRwx,
+            _ if perms.execute => MemoryCategory::AnonymousExec,
+            _ => MemoryCategory::Anonymous,
+        }
+    }
+
+    pub fn kind(&self) -> MappingKind {
+        let Some(path) = self.path.as_deref() else {
+            return MappingKind::Anonymous;
+        };
+
+        match path {
+            "[heap]" => MappingKind::Heap,
+            "[vdso]" => MappingKind::Vdso,
+            "[vvar]" | "[vvar_vclock]" => MappingKind::Vvar,
+            "[vsyscall]" => MappingKind::Vsyscall,
+            _ if path == "[stack]" || path.starts_with("[stack:") => MappingKind::Stack,
+            _ if path.starts_with("[anon:") || path.starts_with("[anon_shmem:") => {
+                MappingKind::Anonymous
+            }
+            _ if path.starts_with("/memfd:") => MappingKind::Memfd,
+            _ if path.starts_with("/SYSV") || path.starts_with("/dev/shm/") => MappingKind::Shm,
+            _ if path.starts_with('/') => MappingKind::File,
+            _ => MappingKind::Pseudo,
+        }
+    }
+
+    /// The path as the kernel writes it, marked if the file was deleted.
+    fn kernel_path(&self) -> Option<Cow<'_, str>> {
+        let path = self.path.as_deref()?;
+        Some(if self.deleted {
+            Cow::Owned(format!("{}{}", path, DELETED_SUFFIX))
+        } else {
+            Cow::Borrowed(path)
+        })
+    }
+
+    /// A hash identifying the map between readings of the same process, regardless of
+    /// how its sizes or end address change. Maps of different processes, such as forked
+    /// workers that share their layout, differ by their `pid`.
+    pub fn identity(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.pid.hash(&mut hasher);
+        self.address_range.from.hash(&mut hasher);
+        self.offset.hash(&mut hasher);
+        self.device.hash(&mut hasher);
+        self.inode.hash(&mut hasher);
+        self.path.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// A rough Pss for kernels that don't report one, assuming every shared page is
+    /// mapped by `share_count` processes. The whole of a shared (`s`) map is divided,
+    /// otherwise just its Shared_Clean and Shared_Dirty.
+    pub fn estimated_pss(&self, share_count: usize) -> usize {
+        let share_count = share_count.max(1);
+        let rss = kb(&self.sizes.rss);
+        if self.permissions.shared {
+            return rss / share_count;
+        }
+
+        let shared = kb(&self.sizes.shared_clean) + kb(&self.sizes.shared_dirty);
+        rss.saturating_sub(shared) + shared / share_count
+    }
+
+    /// Check the sizes are consistent with each other and with the address range.
+    pub fn invariants(&self) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let size = kb(&self.sizes.size);
+        let rss = kb(&self.sizes.rss);
+        let pss = kb(&self.sizes.pss);
+
+        if rss > size {
+            violations.push(InvariantViolation::RssExceedsSize { rss, size });
+        }
+
+        if pss > rss {
+            violations.push(InvariantViolation::PssExceedsRss { pss, rss });
+        }
+
+        let breakdown = kb(&self.sizes.private_clean)
+            + kb(&self.sizes.private_dirty)
+            + kb(&self.sizes.shared_clean)
+            + kb(&self.sizes.shared_dirty);
+        if breakdown > rss {
+            violations.push(InvariantViolation::BreakdownExceedsRss { breakdown, rss });
+        }
+
+        let range = self.address_range.len() / 1024;
+        let page = self
+            .sizes
+            .kernel_page_size
+            .as_ref()
+            .map_or(4, Numeric::value);
+        if self.size().is_some() && size.abs_diff(range) > page {
+            violations.push(InvariantViolation::SizeMismatch { size, range });
+        }
+
+        violations
+    }
+
+    /// The first line of the map as it's displayed, with the flags before the path.
+    pub fn heading(&self) -> String {
+        format!(
+            "{} {} {:08x} {} {} {} {}",
+            self.address_range,
+            self.permissions,
+            self.offset,
+            self.device,
+            self.inode,
+            self.vm_flags,
+            self.kernel_path().unwrap_or_default()
+        )
+    }
+
+    /// The main fields of the map on one line, padded so that lines for different maps
+    /// line up with each other.
+    pub fn compact_line(&self, units: Units) -> String {
+        let field = |size: &Option<Numeric>| {
+            size.as_ref()
+                .map(|size| units.format(size.value()))
+                .unwrap_or_default()
+        };
+
+        format!(
+            "{:<33} {} size={:>11} rss={:>11} pss={:>11} swap={:>11} {}",
+            self.address_range.to_string(),
+            self.permissions,
+            field(&self.sizes.size),
+            field(&self.sizes.rss),
+            field(&self.sizes.pss),
+            field(&self.sizes.swap),
+            self.kernel_path().unwrap_or_default()
+        )
+    }
+
+    /// Format the map the way the kernel writes it in smaps, so a faithful parse of a
+    /// capture writes it back out unchanged. The fields go in kernel order, with any
+    /// unknown ones after in name order.
+    pub fn to_smaps(&self) -> String {
+        let mut smaps = format!(
+            "{} {} {:08x} {} {} ",
+            self.address_range, self.permissions, self.offset, self.device, self.inode
+        );
+        if let Some(path) = self.kernel_path() {
+            // The kernel pads the path out to a column, as wide as needed for 64 bit
+            // addresses.
+            smaps = format!("{:<72} {}", smaps, path);
+        }
+        smaps.push('\n');
+
+        let known = SIZE_FIELDS.iter().chain(NUMBER_FIELDS);
+        let unknown = self
+            .sizes
+            .extra
+            .keys()
+            .chain(self.flags.keys())
+            .filter(|field| !NUMBER_FIELDS.contains(&field.as_str()));
+        for field in known.copied().chain(unknown.map(String::as_str)) {
+            let label = format!("{}:", field);
+            if let Some(size) = self.sizes.get(field) {
+                smaps.push_str(&format!("{:<16}{:>8} kB\n", label, size.value()));
+            } else if let Some(number) = self.flags.get(field) {
+                smaps.push_str(&format!("{:<16}{:>8}\n", label, number));
+            }
+        }
+
+        // Each flag is followed by a space, even the last.
+        smaps.push_str("VmFlags: ");
+        for flag in &self.vm_flags.0 {
+            smaps.push_str(flag.mnemonic());
+            smaps.push(' ');
+        }
+        smaps.push('\n');
+        smaps
+    }
+
+    /// Format the map the way `pmap` lists it, eg.
+    /// `00007f1475c00000    1368K r-x-- /usr/lib/libc.so.6`.
+    pub fn to_pmap_line(&self) -> String {
+        let perms = self.permissions;
+        let flag = |set, c| if set { c } else { '-' };
+        let mode = format!(
+            "{}{}{}{}-",
+            flag(perms.read, 'r'),
+            flag(perms.write, 'w'),
+            flag(perms.execute, 'x'),
+            flag(perms.shared, 's'),
+        );
+
+        format!(
+            "{:016x} {:>7}K {} {}",
+            self.address_range.from,
+            self.pmap_size(),
+            mode,
+            self.kernel_path().as_deref().unwrap_or("  [ anon ]")
+        )
+    }
+
+    /// The size in kB, falling back to the length of the range if smaps didn't give one.
+    pub fn pmap_size(&self) -> usize {
+        self.size()
+            .unwrap_or_else(|| self.address_range.len() / 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uss_and_shared() {
+        let m = "1000-2000 rw-p 00000000 00:00 0\nShared_Dirty: 4 kB\nPrivate_Clean: 1 kB\nPrivate_Dirty: 2 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        assert_eq!((Some(3), Some(4)), (m.uss(), m.shared()));
+        let m = "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        assert_eq!((None, None), (m.uss(), m.shared()));
+    }
+
+    #[test]
+    fn test_is_page_granular() {
+        assert!(Numeric::Kb(4).is_page_granular("Rss"));
+        assert!(Numeric::Bytes(4096).is_page_granular("Swap"));
+        assert!(!Numeric::Kb(4).is_page_granular("Pss_Dirty"));
+        assert!(!Numeric::Kb(4).is_page_granular("SwapPss"));
+        assert!(!Numeric::Number(1).is_page_granular("THPeligible"));
+    }
+
+    #[test]
+    fn test_range_contains() {
+        let range = Range {
+            from: 0x1000,
+            to: 0x2000,
+        };
+
+        assert!(!range.contains(0xfff));
+        assert!(range.contains(0x1000));
+        assert!(range.contains(0x1fff));
+        assert!(!range.contains(0x2000));
+        assert_eq!(0x1000, range.len());
+        assert_eq!(
+            0,
+            Range {
+                from: 0x1000,
+                to: 0x1000
+            }
+            .len()
+        );
+    }
+
+    #[test]
+    fn test_range_overlaps() {
+        let range = Range {
+            from: 0x1000,
+            to: 0x2000,
+        };
+
+        // Ranges that only touch at `to` share no addresses.
+        assert!(!range.overlaps(&Range {
+            from: 0x2000,
+            to: 0x3000
+        }));
+        assert!(!range.overlaps(&Range {
+            from: 0x0,
+            to: 0x1000
+        }));
+        assert!(range.overlaps(&Range {
+            from: 0x1fff,
+            to: 0x3000
+        }));
+        assert!(range.overlaps(&Range {
+            from: 0x0,
+            to: 0x1001
+        }));
+        assert!(range.overlaps(&Range {
+            from: 0x1400,
+            to: 0x1800
+        }));
+        assert!(range.overlaps(&Range {
+            from: 0x0,
+            to: 0x3000
+        }));
+    }
+
+    #[test]
+    fn test_range_display_matches_kernel() {
+        for range in [
+            "00400000-00452000",
+            "7a85b6dff000-7a85f6e00000",
+            "ffffffffff600000-ffffffffff601000",
+        ] {
+            assert_eq!(range, Range::try_from(range).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_to_smaps_round_trip() {
+        for smaps in [
+            "55bbdc35e000-55bbdc360000 r--p 00000000 fe:00 280542                     /usr/bin/cat
+Size:                  8 kB
+KernelPageSize:        4 kB
+MMUPageSize:           4 kB
+Rss:                   8 kB
+Pss:                   8 kB
+Private_Clean:         8 kB
+Locked:                0 kB
+THPeligible:           0
+ProtectionKey:         0
+VmFlags: rd mr mw me 
+",
+            "7feefa43d000-7feefa462000 rw-p 00000000 00:00 0 
+Size:                148 kB
+Rss:                  12 kB
+Shiny:                 4 kB
+VmFlags: rd wr mr mw me ac 
+",
+            "7f1c2a000000-7f1c2a021000 r-xp 00000000 fe:00 281234                     /usr/lib/libold.so (deleted)
+Rss:                  20 kB
+VmFlags: rd ex mr mw me 
+",
+        ] {
+            assert_eq!(smaps, smaps.parse::<MemoryMap>().unwrap().to_smaps());
+        }
+    }
+
+    #[test]
+    fn test_category() {
+        let category = |header: &str| {
+            format!("{}\nRss: 4 kB\nVmFlags: rd\n", header)
+                .parse::<MemoryMap>()
+                .unwrap()
+                .category()
+        };
+
+        assert_eq!(
+            MemoryCategory::Code,
+            category("1000-2000 r-xp 00002000 fe:00 280542 /usr/bin/cat")
+        );
+        assert_eq!(
+            MemoryCategory::ReadOnlyData,
+            category("1000-2000 r--p 00000000 fe:00 280542 /usr/bin/cat")
+        );
+        assert_eq!(
+            MemoryCategory::MutableData,
+            category("1000-2000 rw-p 00007000 fe:00 280542 /usr/bin/cat")
+        );
+        assert_eq!(
+            MemoryCategory::Shared,
+            category("1000-2000 rw-s 00000000 00:01 1024 /dev/shm/queue")
+        );
+        assert_eq!(
+            MemoryCategory::Heap,
+            category("1000-2000 rw-p 00000000 00:00 0 [heap]")
+        );
+        assert_eq!(
+            MemoryCategory::Stack,
+            category("1000-2000 rw-p 00000000 00:00 0 [stack]")
+        );
+        assert_eq!(
+            MemoryCategory::Anonymous,
+            category("1000-2000 rw-p 00000000 00:00 0")
+        );
+        assert_eq!(
+            MemoryCategory::AnonymousExec,
+            category("1000-2000 r-xp 00000000 00:00 0")
+        );
+        assert_eq!(
+            MemoryCategory::AnonymousRwx,
+            category("1000-2000 rwxp 00000000 00:00 0")
+        );
+        assert_eq!(
+            MemoryCategory::Guard,
+            category("1000-2000 ---p 00000000 00:00 0")
+        );
+        assert_eq!(
+            MemoryCategory::Kernel,
+            category("1000-2000 r-xp 00000000 00:00 0 [vdso]")
+        );
+    }
+
+    #[test]
+    fn test_permissions() {
+        let perms = Permissions::try_from("rwxp").unwrap();
+        assert!(perms.read && perms.write && perms.execute && !perms.shared);
+        assert!(perms.is_writable_and_executable());
+        assert_eq!("rwxp", perms.to_string());
+
+        let perms = Permissions::try_from("r--s").unwrap();
+        assert!(!perms.is_writable_and_executable());
+        assert_eq!("r--s", perms.to_string());
+
+        assert!(Permissions::try_from("---p").unwrap().is_none());
+        assert!(Permissions::try_from("rw-").is_err());
+        assert!(Permissions::try_from("rwxq").is_err());
+        assert!(Permissions::try_from("xw-p").is_err());
+    }
+
+    #[test]
+    fn test_permission_pattern() {
+        let matches = |pattern: &str, perms: &str| {
+            pattern
+                .parse::<PermissionPattern>()
+                .unwrap()
+                .matches(&Permissions::try_from(perms).unwrap())
+        };
+        assert!(matches("rw-p", "rw-p"));
+        assert!(!matches("rw-p", "rwxp"));
+        assert!(matches("r?x?", "r-xs"));
+        assert!(matches("wx", "rwxp"));
+        assert!(!matches("wx", "rw-p"));
+        assert!(matches("xs", "r-xs"));
+        assert!(!matches("xs", "r-xp"));
+
+        assert!("".parse::<PermissionPattern>().is_err());
+        assert!("sp".parse::<PermissionPattern>().is_err());
+        assert!("rwq".parse::<PermissionPattern>().is_err());
+    }
+
+    #[test]
+    fn test_kind() {
+        let kind = |path: &str| {
+            format!("1000-2000 rw-p 00000000 00:00 0 {}\nRss: 4 kB\n", path)
+                .parse::<MemoryMap>()
+                .unwrap()
+                .kind()
+        };
+
+        assert_eq!(MappingKind::Heap, kind("[heap]"));
+        assert_eq!(MappingKind::Stack, kind("[stack]"));
+        assert_eq!(MappingKind::Vdso, kind("[vdso]"));
+        assert_eq!(MappingKind::Vvar, kind("[vvar]"));
+        assert_eq!(MappingKind::Vsyscall, kind("[vsyscall]"));
+        assert_eq!(MappingKind::Anonymous, kind(""));
+        assert_eq!(MappingKind::Anonymous, kind("[anon:jemalloc]"));
+        assert_eq!(MappingKind::File, kind("/usr/lib/libc.so.6"));
+        assert_eq!(MappingKind::Memfd, kind("/memfd:wayland-shm (deleted)"));
+        assert_eq!(MappingKind::Shm, kind("/SYSV00000000"));
+        assert_eq!(MappingKind::Shm, kind("/dev/shm/pulse-shm-123"));
+        assert_eq!(MappingKind::Pseudo, kind("[uprobes]"));
+
+        assert_eq!(Ok(MappingKind::Memfd), "memfd".parse());
+        assert_eq!("memfd", MappingKind::Memfd.to_string());
+        assert!("socket".parse::<MappingKind>().is_err());
+
+        assert!(kind("[anon:jemalloc]").is_anonymous());
+        assert!(!kind("/memfd:wayland (deleted)").is_anonymous());
+        assert!(kind("/memfd:wayland (deleted)").is_file_backed());
+        assert!(!kind("[vdso]").is_anonymous() && !kind("[vdso]").is_file_backed());
+    }
+
+    #[test]
+    fn test_device() {
+        let device = Device::try_from("fe:0a").unwrap();
+        assert_eq!(
+            Device {
+                major: 0xfe,
+                minor: 0x0a
+            },
+            device
+        );
+        assert_eq!("fe:0a", device.to_string());
+        assert_eq!("00:00", Device::default().to_string());
+        assert!(Device::try_from("fe").is_err());
+        assert!(Device::try_from("fe:zz").is_err());
+    }
+
+    #[test]
+    fn test_vm_flags() {
+        let flags = VmFlags::from("rd wr mr mw me lo ht zz ");
+        assert!(flags.contains(&VmFlag::Locked));
+        assert!(flags.contains(&VmFlag::HugeTlb));
+        assert!(!flags.contains(&VmFlag::Exec));
+        assert_eq!(Some(&VmFlag::Unknown("zz".to_string())), flags.0.last());
+        assert_eq!("rd wr mr mw me lo ht zz", flags.to_string());
+        assert!(VmFlags::from("").is_empty());
+    }
+
+    #[test]
+    fn test_smaps_fields() {
+        let mut sizes = SmapsFields::default();
+        sizes.insert("Pss_Dirty".to_string(), Numeric::Kb(8));
+        sizes.insert("Size".to_string(), Numeric::Kb(12));
+        sizes.insert("Future_Field".to_string(), Numeric::Kb(4));
+
+        assert_eq!(Some(Numeric::Kb(8)), sizes.pss_dirty);
+        assert_eq!(Some(&Numeric::Kb(4)), sizes.get("Future_Field"));
+        assert_eq!(None, sizes.get("Rss"));
+        assert_eq!(
+            vec!["Size", "Pss_Dirty", "Future_Field"],
+            sizes.iter().map(|(field, _)| field).collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_smaps_fields_serde() {
+        let mut sizes = SmapsFields::default();
+        sizes.insert("Size".to_string(), Numeric::Kb(12));
+        sizes.insert("Pss_Dirty".to_string(), Numeric::Kb(8));
+        sizes.insert("Future_Field".to_string(), Numeric::Kb(4));
+
+        // Snapshots keep the kernel's names for the fields.
+        let json = serde_json::to_string(&sizes).unwrap();
+        assert_eq!(
+            r#"{"Size":{"Kb":12},"Pss_Dirty":{"Kb":8},"Future_Field":{"Kb":4}}"#,
+            json
+        );
+        assert_eq!(sizes, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_pmap_size() {
+        let m = |line: &str| line.parse::<MemoryMap>().unwrap();
+        assert_eq!(8, m("1000-3000 rw-p 00000000 00:00 0\n").pmap_size());
+        assert_eq!(
+            4,
+            m("1000-3000 rw-p 00000000 00:00 0\nSize: 4 kB\n").pmap_size()
+        );
+        // A backwards range from a damaged capture is empty rather than a panic.
+        assert_eq!(
+            4,
+            m("3000-1000 rw-p 00000000 00:00 0\nSize: 4 kB\n").pmap_size()
+        );
+        assert_eq!(0, m("3000-1000 rw-p 00000000 00:00 0\n").pmap_size());
+    }
+
+    #[test]
+    fn test_compact_line() {
+        let m = |line: &str| line.parse::<MemoryMap>().unwrap();
+        let libc = m("1000-3000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\nSize: 8 kB\nRss: 8 kB\nPss: 2 kB\n");
+        let anon = m("ffff1000-ffff2000 rw-p 00000000 00:00 0\nSize: 1024 kB\nSwap: 4 kB\n");
+        assert_eq!(
+            "00001000-00003000                 r-xp size=       8 kB rss=       8 kB pss=       2 kB swap=            /usr/lib/libc.so.6",
+            libc.compact_line(Units::Kb)
+        );
+        assert_eq!(
+            "ffff1000-ffff2000                 rw-p size=    1024 kB rss=            pss=            swap=       4 kB ",
+            anon.compact_line(Units::Kb)
+        );
+    }
+
+    #[test]
+    fn test_estimated_pss() {
+        let private = "1000-5000 rw-p 00000000 00:00 0 
+Rss:                  16 kB
+Private_Dirty:         8 kB
+Shared_Clean:          8 kB
+VmFlags: rd wr
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+        assert_eq!(10, private.estimated_pss(4));
+
+        let shared = "1000-5000 rw-s 00000000 00:00 0 
+Rss:                  16 kB
+Private_Dirty:        16 kB
+VmFlags: rd wr sh
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+        assert_eq!(4, shared.estimated_pss(4));
+        assert_eq!(16, shared.estimated_pss(0));
+    }
+
+    #[test]
+    fn test_invariants() {
+        let valid = "1000-3000 rw-p 00000000 00:00 0 
+Size:                  8 kB
+Rss:                   8 kB
+Pss:                   4 kB
+Shared_Clean:          8 kB
+VmFlags: rd wr
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+        assert_eq!(Vec::<InvariantViolation>::new(), valid.invariants());
+
+        let torn = "1000-3000 rw-p 00000000 00:00 0 
+Size:                 16 kB
+Rss:                  20 kB
+Pss:                  24 kB
+Private_Dirty:        24 kB
+VmFlags: rd wr
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+        assert_eq!(
+            vec![
+                InvariantViolation::RssExceedsSize { rss: 20, size: 16 },
+                InvariantViolation::PssExceedsRss { pss: 24, rss: 20 },
+                InvariantViolation::BreakdownExceedsRss {
+                    breakdown: 24,
+                    rss: 20
+                },
+                InvariantViolation::SizeMismatch { size: 16, range: 8 },
+            ],
+            torn.invariants()
+        );
     }
 }
@@ -1,31 +1,41 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while, take_while1},
+    bytes::complete::{tag, take_while, take_while1},
     character::complete::{digit1, newline, space0, space1},
-    combinator::{map, map_res, opt},
-    multi::separated_list1,
+    combinator::{eof, map, map_res, opt},
+    multi::{many0, separated_list1},
     sequence::{preceded, terminated, tuple},
     IResult,
 };
 
-use crate::memory_map::{MemoryMap, Numeric, Range};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, BufRead},
+    str::FromStr,
+};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::memory_map::{
+    self, Device, InvariantViolation, MemoryMap, Numeric, Permissions, Range, SmapsFields, VmFlags,
+};
 
 fn parse_size(input: &str) -> IResult<&str, Numeric> {
     map_res(terminated(digit1, tag(" kB")), |s: &str| {
-        s.parse::<usize>().map(|num| Numeric::Kb(num))
+        s.parse::<usize>().map(Numeric::Kb)
     })(input)
 }
 
 fn parse_number(input: &str) -> IResult<&str, Numeric> {
-    map_res(digit1, |s: &str| {
-        s.parse::<usize>().map(|num| Numeric::Number(num))
-    })(input)
+    map_res(digit1, |s: &str| s.parse::<usize>().map(Numeric::Number))(input)
 }
 
 fn parse_memory_line(input: &str) -> IResult<&str, (String, Numeric)> {
     map(
         tuple((
-            take_until(":"),
+            take_while1(|c| c != ':' && c != ' ' && c != '\n'),
             tag(":"),
             space1,
             alt((parse_size, parse_number)),
@@ -34,10 +44,10 @@ fn parse_memory_line(input: &str) -> IResult<&str, (String, Numeric)> {
     )(input)
 }
 
-fn parse_vm_flags(input: &str) -> IResult<&str, String> {
+fn parse_vm_flags(input: &str) -> IResult<&str, VmFlags> {
     map(
         tuple((tag("VmFlags:"), space0, take_while1(|c| c != '\n'))),
-        |(_, _, flags): (&str, &str, &str)| flags.to_string(),
+        |(_, _, flags): (&str, &str, &str)| VmFlags::from(flags),
     )(input)
 }
 
@@ -53,47 +63,490 @@ fn parse_memory_range(input: &str) -> IResult<&str, Range> {
     })(input)
 }
 
-pub fn parse_memory_map(input: &str) -> IResult<&str, MemoryMap> {
-    let (input, (address_range, permissions, offset, device, inode, path, _)) = tuple((
+/// Parse the header line of a map, leaving the sizes empty.
+fn parse_header(input: &str) -> IResult<&str, MemoryMap> {
+    let (input, (address_range, permissions, offset, device, inode, path)) = tuple((
         parse_memory_range,
-        preceded(space1, take_while1(|c| c != ' ')),
-        preceded(space1, take_while1(|c| c != ' ')),
-        preceded(space1, take_while1(|c| c != ' ')),
-        preceded(space1, take_while1(|c| c != ' ')),
-        preceded(space1, take_while(|c| c != '\n')),
-        tag("\n"),
+        preceded(
+            space1,
+            map_res(take_while1(|c| c != ' '), Permissions::try_from),
+        ),
+        preceded(
+            space1,
+            map_res(take_while1(|c| c != ' '), |num| {
+                u64::from_str_radix(num, 16)
+            }),
+        ),
+        preceded(space1, map_res(take_while1(|c| c != ' '), Device::try_from)),
+        preceded(space1, map_res(digit1, str::parse::<u64>)),
+        opt(preceded(space1, take_while(|c| c != '\n'))),
     ))(input)?;
 
-    let (input, sizes) = separated_list1(newline, parse_memory_line)(input)?;
-
-    let (input, _) = tag("\n")(input)?;
-    let sizes = sizes.into_iter().collect();
-    let (input, vm_flags) = parse_vm_flags(input)?;
-
-    let (input, _) = opt(tag("\n"))(input)?;
+    let path = path.filter(|path| !path.is_empty());
+    let deleted_path = path.and_then(|path| path.strip_suffix(memory_map::DELETED_SUFFIX));
 
     Ok((
         input,
         MemoryMap {
             address_range,
-            permissions: permissions.to_string(),
-            offset: offset.to_string(),
-            device: device.to_string(),
-            inode: inode.to_string(),
-            path: if path.is_empty() {
-                None
-            } else {
-                Some(path.to_string())
-            },
-            sizes,
-            vm_flags,
+            permissions,
+            offset,
+            device,
+            inode,
+            path: deleted_path.or(path).map(str::to_string),
+            deleted: deleted_path.is_some(),
+            sizes: SmapsFields::default(),
+            flags: BTreeMap::new(),
+            vm_flags: VmFlags::default(),
+            pid: None,
         },
     ))
 }
 
+fn insert_sizes(memory_map: &mut MemoryMap, sizes: Vec<(String, Numeric)>) {
+    // Only the kB fields are sizes, bare numbers such as ProtectionKey are kept apart so
+    // they don't end up in any totals.
+    for (label, value) in sizes {
+        match value {
+            Numeric::Kb(_) | Numeric::Bytes(_) => {
+                memory_map.sizes.insert(label, value);
+            }
+            Numeric::Number(num) => {
+                memory_map.flags.insert(label, num);
+            }
+        }
+    }
+}
+
+/// Parse `/proc/<pid>/smaps_rollup`, which is a single map block summing every map of
+/// the process, without a Size or VmFlags.
+pub fn parse_rollup(input: &str) -> Result<MemoryMap, &'static str> {
+    let mut parser = tuple((
+        terminated(parse_header, tag("\n")),
+        separated_list1(newline, parse_memory_line),
+    ));
+    let (rest, (mut memory_map, sizes)) = parser(input).map_err(|_| "cant parse rollup")?;
+    if !rest.trim().is_empty() {
+        return Err("trailing content after rollup");
+    }
+
+    insert_sizes(&mut memory_map, sizes);
+    Ok(memory_map)
+}
+
+fn end_of_line(input: &str) -> IResult<&str, &str> {
+    alt((tag("\n"), eof))(input)
+}
+
+/// Parse a map block. Kernels have added fields over the years, so every field is
+/// optional, VmFlags included, and any that aren't known are kept as they are.
+pub fn parse_memory_map(input: &str) -> IResult<&str, MemoryMap> {
+    let (input, mut memory_map) = terminated(parse_header, end_of_line)(input)?;
+
+    let (input, sizes) = many0(terminated(parse_memory_line, end_of_line))(input)?;
+    insert_sizes(&mut memory_map, sizes);
+
+    let (input, vm_flags) = opt(terminated(parse_vm_flags, end_of_line))(input)?;
+    memory_map.vm_flags = vm_flags.unwrap_or_default();
+
+    // Anything but the next map means this one was cut short or has a line that isn't
+    // a field.
+    if !input.trim().is_empty() && !is_header(input) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((input, memory_map))
+}
+
+/// A line of input that couldn't be parsed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ParseError {
+    /// Counting from 1.
+    pub line: usize,
+    pub content: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cant parse line {}: {}", self.line, self.content)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse `/proc/<pid>/maps`, which only has the header line of each map. The maps have
+/// no sizes or VmFlags.
+pub fn parse_maps(input: &str) -> Result<Vec<MemoryMap>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| match parse_header(line) {
+            Ok(("", memory_map)) => Ok(memory_map),
+            _ => Err(ParseError {
+                line: idx + 1,
+                content: line.to_string(),
+            }),
+        })
+        .collect()
+}
+
+/// Split a log of several captures on the lines starting with `delimiter`. Each
+/// capture comes with the delimiter line before it, if there was one. Captures that are
+/// only whitespace are dropped.
+pub fn split_snapshots<'a>(input: &'a str, delimiter: &str) -> Vec<(Option<&'a str>, &'a str)> {
+    let mut snapshots = Vec::new();
+    let mut marker = None;
+    let mut start = 0;
+
+    let mut offset = 0;
+
+    for line in input.split_inclusive('\n') {
+        if line.starts_with(delimiter) {
+            snapshots.push((marker, &input[start..offset]));
+            marker = Some(line.trim_end());
+            start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    snapshots.push((marker, &input[start..]));
+
+    snapshots.retain(|(_, section)| !section.trim().is_empty());
+    snapshots
+}
+
+/// Parses exactly one map block, failing if anything other than whitespace follows it.
+impl FromStr for MemoryMap {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rest, memory_map) = parse_memory_map(s).map_err(|_| "cant parse")?;
+        if rest.trim().is_empty() {
+            Ok(memory_map)
+        } else {
+            Err("trailing content after memory map")
+        }
+    }
+}
+
+/// Something odd about a map that didn't stop it being parsed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(tag = "warning", rename_all = "snake_case")
+)]
+pub enum Warning {
+    /// A field that isn't in `SIZE_FIELDS` or `NUMBER_FIELDS`, likely from a newer kernel.
+    UnknownField { map: Range, field: String },
+    Invariant {
+        map: Range,
+        violation: InvariantViolation,
+    },
+    /// Only checked by strict parses, as older captures and tests often leave fields out.
+    MissingField { map: Range, field: &'static str },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnknownField { map, field } => write!(f, "{}: unknown field {}", map, field),
+            Warning::Invariant { map, violation } => write!(f, "{}: {}", map, violation),
+            Warning::MissingField { map, field } => write!(f, "{}: missing field {}", map, field),
+        }
+    }
+}
+
+/// The warnings about a single map.
+pub fn check(m: &MemoryMap) -> Vec<Warning> {
+    let unknown = m
+        .sizes
+        .extra
+        .keys()
+        .chain(
+            m.flags
+                .keys()
+                .filter(|field| !memory_map::NUMBER_FIELDS.contains(&field.as_str())),
+        )
+        .map(|field| Warning::UnknownField {
+            map: m.address_range.clone(),
+            field: field.clone(),
+        });
+    let invariants = m
+        .invariants()
+        .into_iter()
+        .map(|violation| Warning::Invariant {
+            map: m.address_range.clone(),
+            violation,
+        });
+
+    unknown.chain(invariants).collect()
+}
+
+/// The fields every kernel since 4.14 gives for each map.
+const EXPECTED_FIELDS: &[&str] = &[
+    "Size",
+    "KernelPageSize",
+    "MMUPageSize",
+    "Rss",
+    "Pss",
+    "Shared_Clean",
+    "Shared_Dirty",
+    "Private_Clean",
+    "Private_Dirty",
+    "Referenced",
+    "Anonymous",
+    "AnonHugePages",
+    "Swap",
+    "SwapPss",
+    "Locked",
+];
+
+fn missing_fields(m: &MemoryMap) -> impl Iterator<Item = Warning> + '_ {
+    EXPECTED_FIELDS
+        .iter()
+        .filter(|field| m.sizes.get(field).is_none())
+        .map(|field| Warning::MissingField {
+            map: m.address_range.clone(),
+            field,
+        })
+}
+
+/// Every map in a smaps file along with the warnings about them.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ParseResult {
+    pub maps: Vec<MemoryMap>,
+    pub warnings: Vec<Warning>,
+}
+
+/// Why `parse_all` gave up.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ParseAllError {
+    Parse(ParseError),
+    /// In strict mode, a warning about the map whose header is on `line`.
+    Strict {
+        line: usize,
+        warning: Warning,
+    },
+    /// In strict mode, anything left after the last map, even blank lines.
+    Leftover {
+        line: usize,
+    },
+}
+
+impl Display for ParseAllError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseAllError::Parse(err) => write!(f, "{}", err),
+            ParseAllError::Strict { line, warning } => write!(f, "line {}: {}", line, warning),
+            ParseAllError::Leftover { line } => {
+                write!(f, "line {}: unexpected content after the last map", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseAllError {}
+
+/// Parse every map, gathering the warnings about them. With `strict` set, the first of
+/// these fails the parse instead:
+///
+/// - a field that isn't in `SIZE_FIELDS` or `NUMBER_FIELDS`
+/// - a map missing one of the fields every kernel since 4.14 gives
+/// - a map breaking one of the `invariants`
+/// - any content after the last map, including blank lines
+pub fn parse_all(input: &str, strict: bool) -> Result<ParseResult, ParseAllError> {
+    let mut result = ParseResult::default();
+    let mut parser = MemoryMaps::new(input);
+    let mut line = 1;
+    let mut consumed = 0;
+
+    while let Some(memory_map) = parser.next() {
+        let memory_map = memory_map.map_err(ParseAllError::Parse)?;
+
+        let mut warnings = check(&memory_map);
+        if strict {
+            warnings.extend(missing_fields(&memory_map));
+            if let Some(warning) = warnings.into_iter().next() {
+                return Err(ParseAllError::Strict { line, warning });
+            }
+        } else {
+            result.warnings.extend(warnings);
+        }
+        result.maps.push(memory_map);
+
+        // Counted as we go, counting from the start for every map is quadratic.
+        line += input[consumed..parser.consumed()].matches('\n').count();
+        consumed = parser.consumed();
+    }
+
+    if strict && consumed < input.len() {
+        return Err(ParseAllError::Leftover { line });
+    }
+    Ok(result)
+}
+
+/// Parses the maps out of a smaps file one at a time, so callers can process them
+/// without first collecting the whole file into a `Vec`.
+pub struct MemoryMaps<'a> {
+    whole: &'a str,
+    input: &'a str,
+}
+
+impl<'a> MemoryMaps<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            whole: input,
+            input,
+        }
+    }
+
+    /// How many bytes of the input have been parsed so far.
+    pub fn consumed(&self) -> usize {
+        self.whole.len() - self.input.len()
+    }
+
+    /// The line the parser is up to, counting from 1.
+    fn line(&self) -> usize {
+        self.whole[..self.consumed()].matches('\n').count() + 1
+    }
+}
+
+impl Iterator for MemoryMaps<'_> {
+    type Item = Result<MemoryMap, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.trim().is_empty() {
+            return None;
+        }
+
+        match parse_memory_map(self.input) {
+            Ok((rest, memory_map)) => {
+                self.input = rest;
+                Some(Ok(memory_map))
+            }
+            Err(_) => {
+                let error = ParseError {
+                    line: self.line(),
+                    content: self.input.lines().next().unwrap_or_default().to_string(),
+                };
+                // Nothing after a failure can be trusted to start on a map boundary.
+                self.input = "";
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Why `ReadMaps` stopped.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "{}", err),
+            ReadError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Whether the line starts a new map, rather than being one of the fields of the last.
+fn is_header(line: &str) -> bool {
+    parse_memory_range(line).is_ok_and(|(rest, _)| rest.starts_with(' '))
+}
+
+/// Parses the maps out of a smaps file as it's read, so only one map is ever held in
+/// memory rather than the whole file. A map runs up to the header line of the next.
+pub struct ReadMaps<R> {
+    reader: R,
+    /// The header line of the next map and its line number, read while looking for
+    /// the end of the last one.
+    header: Option<(usize, String)>,
+    lines: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ReadMaps<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            header: None,
+            lines: 0,
+            done: false,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.lines += 1;
+        Ok(Some(line))
+    }
+
+    /// Read the next map's lines, or `None` at the end of the input.
+    fn read_block(&mut self) -> io::Result<Option<(usize, String)>> {
+        let (line, mut block) = match self.header.take() {
+            Some(header) => header,
+            None => loop {
+                match self.read_line()? {
+                    Some(line) if line.trim().is_empty() => {}
+                    Some(line) => break (self.lines, line),
+                    None => return Ok(None),
+                }
+            },
+        };
+
+        while let Some(next) = self.read_line()? {
+            if is_header(&next) {
+                self.header = Some((self.lines, next));
+                break;
+            }
+            block.push_str(&next);
+        }
+        Ok(Some((line, block)))
+    }
+}
+
+impl<R: BufRead> Iterator for ReadMaps<R> {
+    type Item = Result<MemoryMap, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = match self.read_block() {
+            Ok(None) => return None,
+            Ok(Some((line, block))) => match parse_memory_map(&block) {
+                Ok((rest, memory_map)) if rest.trim().is_empty() => Ok(memory_map),
+                _ => Err(ReadError::Parse(ParseError {
+                    line,
+                    content: block.lines().next().unwrap_or_default().to_string(),
+                })),
+            },
+            Err(err) => Err(ReadError::Io(err)),
+        };
+        // Nothing after a failure can be trusted to start on a map boundary.
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory_map::VmFlag;
 
     #[test]
     fn test_parse_line() {
@@ -135,43 +588,53 @@ VmFlags: rd mr mw me sd"#;
 
         let result = parse_memory_map(input);
 
-        let sizes = [
-            ("Size".to_string(), Numeric::Kb(4)),
-            ("KernelPageSize".to_string(), Numeric::Kb(4)),
-            ("MMUPageSize".to_string(), Numeric::Kb(4)),
-            ("Rss".to_string(), Numeric::Kb(4)),
-            ("Pss".to_string(), Numeric::Kb(4)),
-            ("Pss_Dirty".to_string(), Numeric::Kb(0)),
-            ("Shared_Clean".to_string(), Numeric::Kb(0)),
-            ("Shared_Dirty".to_string(), Numeric::Kb(0)),
-            ("Private_Clean".to_string(), Numeric::Kb(4)),
-            ("Private_Dirty".to_string(), Numeric::Kb(0)),
-            ("Referenced".to_string(), Numeric::Kb(4)),
-            ("Anonymous".to_string(), Numeric::Kb(0)),
-            ("LazyFree".to_string(), Numeric::Kb(0)),
-            ("AnonHugePages".to_string(), Numeric::Kb(0)),
-            ("ShmemPmdMapped".to_string(), Numeric::Kb(0)),
-            ("FilePmdMapped".to_string(), Numeric::Kb(0)),
-            ("Shared_Hugetlb".to_string(), Numeric::Kb(0)),
-            ("Private_Hugetlb".to_string(), Numeric::Kb(0)),
-            ("Swap".to_string(), Numeric::Kb(0)),
-            ("SwapPss".to_string(), Numeric::Kb(0)),
-            ("Locked".to_string(), Numeric::Kb(0)),
-            ("THPeligible".to_string(), Numeric::Number(0)),
-            ("ProtectionKey".to_string(), Numeric::Number(0)),
+        let sizes = SmapsFields {
+            size: Some(Numeric::Kb(4)),
+            kernel_page_size: Some(Numeric::Kb(4)),
+            mmu_page_size: Some(Numeric::Kb(4)),
+            rss: Some(Numeric::Kb(4)),
+            pss: Some(Numeric::Kb(4)),
+            pss_dirty: Some(Numeric::Kb(0)),
+            shared_clean: Some(Numeric::Kb(0)),
+            shared_dirty: Some(Numeric::Kb(0)),
+            private_clean: Some(Numeric::Kb(4)),
+            private_dirty: Some(Numeric::Kb(0)),
+            referenced: Some(Numeric::Kb(4)),
+            anonymous: Some(Numeric::Kb(0)),
+            lazy_free: Some(Numeric::Kb(0)),
+            anon_huge_pages: Some(Numeric::Kb(0)),
+            shmem_pmd_mapped: Some(Numeric::Kb(0)),
+            file_pmd_mapped: Some(Numeric::Kb(0)),
+            shared_hugetlb: Some(Numeric::Kb(0)),
+            private_hugetlb: Some(Numeric::Kb(0)),
+            swap: Some(Numeric::Kb(0)),
+            swap_pss: Some(Numeric::Kb(0)),
+            locked: Some(Numeric::Kb(0)),
+            ..SmapsFields::default()
+        };
+
+        let flags = [
+            ("THPeligible".to_string(), 0),
+            ("ProtectionKey".to_string(), 0),
         ]
         .into_iter()
         .collect();
 
         let expected = MemoryMap {
             address_range: Range::try_from("6ff1475c000-56ff1475d000").unwrap(),
-            permissions: "r--p".to_string(),
-            offset: "00000000".to_string(),
-            device: "fc:06".to_string(),
-            inode: "13134476".to_string(),
+            permissions: Permissions::try_from("r--p").unwrap(),
+            offset: 0,
+            device: Device {
+                major: 0xfc,
+                minor: 0x06,
+            },
+            inode: 13134476,
             path: Some("/home/stephenwakely/src/c/usememory/a.out".to_string()),
+            deleted: false,
             sizes,
-            vm_flags: "rd mr mw me sd".to_string(),
+            flags,
+            vm_flags: VmFlags::from("rd mr mw me sd"),
+            pid: None,
         };
 
         assert_eq!(expected, result.unwrap().1);
@@ -205,47 +668,369 @@ THPeligible:    0
 ProtectionKey:         0
 VmFlags: rd wr mr mw me ac sd"#;
 
-        let sizes = [
-            ("Size".to_string(), Numeric::Kb(1048580)),
-            ("KernelPageSize".to_string(), Numeric::Kb(4)),
-            ("MMUPageSize".to_string(), Numeric::Kb(4)),
-            ("Rss".to_string(), Numeric::Kb(1028)),
-            ("Pss".to_string(), Numeric::Kb(1028)),
-            ("Pss_Dirty".to_string(), Numeric::Kb(1028)),
-            ("Shared_Clean".to_string(), Numeric::Kb(0)),
-            ("Shared_Dirty".to_string(), Numeric::Kb(0)),
-            ("Private_Clean".to_string(), Numeric::Kb(0)),
-            ("Private_Dirty".to_string(), Numeric::Kb(1028)),
-            ("Referenced".to_string(), Numeric::Kb(1028)),
-            ("Anonymous".to_string(), Numeric::Kb(1028)),
-            ("LazyFree".to_string(), Numeric::Kb(0)),
-            ("AnonHugePages".to_string(), Numeric::Kb(0)),
-            ("ShmemPmdMapped".to_string(), Numeric::Kb(0)),
-            ("FilePmdMapped".to_string(), Numeric::Kb(0)),
-            ("Shared_Hugetlb".to_string(), Numeric::Kb(0)),
-            ("Private_Hugetlb".to_string(), Numeric::Kb(0)),
-            ("Swap".to_string(), Numeric::Kb(0)),
-            ("SwapPss".to_string(), Numeric::Kb(0)),
-            ("Locked".to_string(), Numeric::Kb(0)),
-            ("THPeligible".to_string(), Numeric::Number(0)),
-            ("ProtectionKey".to_string(), Numeric::Number(0)),
+        let sizes = SmapsFields {
+            size: Some(Numeric::Kb(1048580)),
+            kernel_page_size: Some(Numeric::Kb(4)),
+            mmu_page_size: Some(Numeric::Kb(4)),
+            rss: Some(Numeric::Kb(1028)),
+            pss: Some(Numeric::Kb(1028)),
+            pss_dirty: Some(Numeric::Kb(1028)),
+            shared_clean: Some(Numeric::Kb(0)),
+            shared_dirty: Some(Numeric::Kb(0)),
+            private_clean: Some(Numeric::Kb(0)),
+            private_dirty: Some(Numeric::Kb(1028)),
+            referenced: Some(Numeric::Kb(1028)),
+            anonymous: Some(Numeric::Kb(1028)),
+            lazy_free: Some(Numeric::Kb(0)),
+            anon_huge_pages: Some(Numeric::Kb(0)),
+            shmem_pmd_mapped: Some(Numeric::Kb(0)),
+            file_pmd_mapped: Some(Numeric::Kb(0)),
+            shared_hugetlb: Some(Numeric::Kb(0)),
+            private_hugetlb: Some(Numeric::Kb(0)),
+            swap: Some(Numeric::Kb(0)),
+            swap_pss: Some(Numeric::Kb(0)),
+            locked: Some(Numeric::Kb(0)),
+            ..SmapsFields::default()
+        };
+
+        let flags = [
+            ("THPeligible".to_string(), 0),
+            ("ProtectionKey".to_string(), 0),
         ]
         .into_iter()
         .collect();
 
         let expected = MemoryMap {
             address_range: Range::try_from("7a85b6dff000-7a85f6e00000").unwrap(),
-            permissions: "rw-p".to_string(),
+            permissions: Permissions::try_from("rw-p").unwrap(),
             path: None,
-            offset: "00000000".to_string(),
-            device: "00:00".to_string(),
-            inode: "0".to_string(),
+            deleted: false,
+            offset: 0,
+            device: Device::default(),
+            inode: 0,
             sizes,
-            vm_flags: "rd wr mr mw me ac sd".to_string(),
+            flags,
+            vm_flags: VmFlags::from("rd wr mr mw me ac sd"),
+            pid: None,
         };
 
         let result = parse_memory_map(input);
 
         assert_eq!(expected, result.unwrap().1);
     }
+
+    #[test]
+    fn test_memory_maps_iterator() {
+        let input = "7a85b6dff000-7a85f6e00000 rw-p 00000000 00:00 0 
+Size:            1048580 kB
+Rss:                1028 kB
+VmFlags: rd wr mr mw me ac sd
+7a85f6e00000-7a85f6e01000 ---p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   0 kB
+VmFlags: mr mw me sd
+";
+
+        let ranges = MemoryMaps::new(input)
+            .map(|m| m.unwrap().address_range.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec!["7a85b6dff000-7a85f6e00000", "7a85f6e00000-7a85f6e01000"],
+            ranges
+        );
+    }
+
+    #[test]
+    fn test_read_maps() {
+        let input = "7a85b6dff000-7a85f6e00000 rw-p 00000000 00:00 0 
+Size:            1048580 kB
+Rss:                1028 kB
+VmFlags: rd wr mr mw me ac sd
+7a85f6e00000-7a85f6e01000 ---p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   0 kB
+VmFlags: mr mw me sd
+";
+
+        let read = ReadMaps::new(io::Cursor::new(input))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let parsed = MemoryMaps::new(input)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(2, read.len());
+        assert_eq!(parsed, read);
+
+        let torn = format!(
+            "{}7a85f6e01000-7a85f6e02000 rw-p 00000000 00:00 0 \nRss",
+            input
+        );
+        let results = ReadMaps::new(io::Cursor::new(torn)).collect::<Vec<_>>();
+        assert_eq!(3, results.len());
+        match &results[2] {
+            Err(ReadError::Parse(err)) => assert_eq!(9, err.line),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        // An old kernel with no VmFlags, THPeligible or ProtectionKey, then a newer
+        // one with fields this doesn't know about.
+        let input = "00400000-0040b000 r-xp 00000000 08:01 1234 /bin/cat
+Size:                 44 kB
+Rss:                  40 kB
+7f0000000000-7f0000001000 rw-p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   4 kB
+Shiny_New:             4 kB
+Generation:            2
+THPeligible:           0
+VmFlags: rd wr mr mw me ac
+7f0000001000-7f0000002000 ---p 00000000 00:00 0";
+
+        let maps = MemoryMaps::new(input)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(3, maps.len());
+        assert_eq!(Some(40), maps[0].rss());
+        assert!(maps[0].vm_flags.is_empty());
+        assert_eq!(Some(&Numeric::Kb(4)), maps[1].sizes.get("Shiny_New"));
+        assert_eq!(Some(&2), maps[1].flags.get("Generation"));
+        assert!(maps[1].vm_flags.contains(&VmFlag::Write));
+        assert_eq!(None, maps[2].size());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let block = "7a85f6e00000-7a85f6e01000 ---p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   0 kB
+VmFlags: mr mw me sd
+";
+
+        let memory_map = block.parse::<MemoryMap>().unwrap();
+        assert_eq!(Some(4), memory_map.size());
+
+        let two_blocks = format!("{}{}", block, block);
+        assert!(two_blocks.parse::<MemoryMap>().is_err());
+        assert!("not a map".parse::<MemoryMap>().is_err());
+    }
+
+    #[test]
+    fn test_number_fields_are_flags() {
+        let input = "ffff8a400000-ffff8a600000 rw-p 00000000 00:00 0 
+Size:               2048 kB
+Rss:                 512 kB
+THPeligible:    1
+ProtectionKey:         3
+MTE_Tag:               7
+VmFlags: rd wr mr mw me ac mt
+";
+
+        let memory_map = parse_memory_map(input).unwrap().1;
+
+        assert_eq!(
+            vec!["Size", "Rss"],
+            memory_map
+                .sizes
+                .iter()
+                .map(|(field, _)| field)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            [
+                ("MTE_Tag".to_string(), 7),
+                ("ProtectionKey".to_string(), 3),
+                ("THPeligible".to_string(), 1),
+            ]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>(),
+            memory_map.flags
+        );
+    }
+
+    #[test]
+    fn test_parse_maps() {
+        let input =
+            "55d5a6a00000-55d5a6a2f000 r--p 00000000 fe:00 280518                     /usr/bin/bash
+7f3d2c21b000-7f3d2c21e000 rw-p 00000000 00:00 0 
+7ffee8c0c000-7ffee8c2e000 rw-p 00000000 00:00 0                          [stack]
+";
+
+        let maps = parse_maps(input).unwrap();
+
+        assert_eq!(3, maps.len());
+        assert_eq!(Some("/usr/bin/bash".to_string()), maps[0].path);
+        assert_eq!(None, maps[1].path);
+        assert_eq!(Some("[stack]".to_string()), maps[2].path);
+        assert_eq!(None, maps[0].size());
+        assert!(maps[0].vm_flags.is_empty());
+
+        assert_eq!(
+            Err(ParseError {
+                line: 2,
+                content: "garbage".to_string()
+            }),
+            parse_maps("7f3d2c21b000-7f3d2c21e000 rw-p 00000000 00:00 0\ngarbage\n")
+        );
+    }
+
+    #[test]
+    fn test_memory_maps_iterator_error() {
+        let input = "7a85f6e00000-7a85f6e01000 ---p 00000000 00:00 0 
+Size:                  4 kB
+VmFlags: mr mw me sd
+7a85f6e01000-7a85f6e02000 ---p 00000000 00:00 0 
+Size:                  4 k";
+
+        let mut maps = MemoryMaps::new(input);
+
+        assert!(maps.next().unwrap().is_ok());
+        assert_eq!(
+            Some(Err(ParseError {
+                line: 4,
+                content: "7a85f6e01000-7a85f6e02000 ---p 00000000 00:00 0 ".to_string()
+            })),
+            maps.next()
+        );
+        assert_eq!(None, maps.next());
+    }
+
+    #[test]
+    fn test_split_snapshots() {
+        let input = "=== one\nfirst\n=== two\n\n=== three\nthird\nmore\n";
+
+        assert_eq!(
+            vec![
+                (Some("=== one"), "first\n"),
+                (Some("=== three"), "third\nmore\n")
+            ],
+            split_snapshots(input, "===")
+        );
+        assert_eq!(
+            vec![(None, "first\n"), (Some("==="), "second")],
+            split_snapshots("first\n===\nsecond", "===")
+        );
+    }
+
+    #[test]
+    fn test_parse_rollup() {
+        let input =
+            "560b42bf9000-7fff43915000 ---p 00000000 00:00 0                          [rollup]
+Rss:                1412 kB
+Pss:                 479 kB
+Anonymous:           104 kB
+Locked:                0 kB
+";
+
+        let rollup = parse_rollup(input).unwrap();
+        assert_eq!(Some("[rollup]"), rollup.path.as_deref());
+        assert_eq!(Some(1412), rollup.rss());
+        assert_eq!(Some(479), rollup.pss());
+        assert_eq!(None, rollup.size());
+    }
+
+    #[test]
+    fn test_parse_all_warnings() {
+        let input = "1000-2000 rw-p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   8 kB
+Shiny:                 4 kB
+VmFlags: rd wr
+2000-3000 rw-p 00000000 00:00 0 
+Size:                  4 kB
+VmFlags: rd wr
+";
+
+        let result = parse_all(input, false).unwrap();
+        let map = Range {
+            from: 0x1000,
+            to: 0x2000,
+        };
+
+        assert_eq!(2, result.maps.len());
+        assert_eq!(
+            vec![
+                Warning::UnknownField {
+                    map: map.clone(),
+                    field: "Shiny".to_string()
+                },
+                Warning::Invariant {
+                    map,
+                    violation: InvariantViolation::RssExceedsSize { rss: 8, size: 4 }
+                }
+            ],
+            result.warnings
+        );
+    }
+
+    #[test]
+    fn test_parse_all_strict() {
+        let full = |extra: &str| {
+            format!(
+                "1000-2000 rw-p 00000000 00:00 0 
+Size:                  4 kB
+KernelPageSize:        4 kB
+MMUPageSize:           4 kB
+Rss:                   4 kB
+Pss:                   4 kB
+Shared_Clean:          0 kB
+Shared_Dirty:          0 kB
+Private_Clean:         0 kB
+Private_Dirty:         4 kB
+Referenced:            4 kB
+Anonymous:             4 kB
+AnonHugePages:         0 kB
+Swap:                  0 kB
+SwapPss:               0 kB
+{}Locked:                0 kB
+VmFlags: rd wr
+",
+                extra
+            )
+        };
+        let map = Range {
+            from: 0x1000,
+            to: 0x2000,
+        };
+
+        assert_eq!(1, parse_all(&full(""), true).unwrap().maps.len());
+        assert_eq!(
+            Err(ParseAllError::Strict {
+                line: 1,
+                warning: Warning::UnknownField {
+                    map: map.clone(),
+                    field: "Shiny".to_string()
+                }
+            }),
+            parse_all(&full("Shiny: 0 kB\n"), true)
+        );
+        assert_eq!(
+            Err(ParseAllError::Strict {
+                line: 18,
+                warning: Warning::MissingField {
+                    map: Range {
+                        from: 0x2000,
+                        to: 0x3000
+                    },
+                    field: "KernelPageSize"
+                }
+            }),
+            parse_all(
+                &format!(
+                    "{}2000-3000 rw-p 00000000 00:00 0 \nSize: 4 kB\nVmFlags: rd\n",
+                    full("")
+                ),
+                true
+            )
+        );
+        assert_eq!(
+            Err(ParseAllError::Leftover { line: 18 }),
+            parse_all(&format!("{}\n", full("")), true)
+        );
+        assert!(parse_all(&format!("{}\n", full("")), false).is_ok());
+    }
 }
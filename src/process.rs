@@ -0,0 +1,358 @@
+use nix::unistd;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs, io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The memory figures from `/proc/<pid>/statm`, all in pages.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Statm {
+    pub size: usize,
+    pub resident: usize,
+    pub shared: usize,
+    pub text: usize,
+    pub data: usize,
+}
+
+impl Statm {
+    /// The resident set size in bytes.
+    pub fn rss(&self) -> usize {
+        self.resident * page_size()
+    }
+}
+
+const PLAUSIBLE_PAGE_SIZES: RangeInclusive<usize> = 1024..=1 << 20;
+
+pub fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| {
+        let sysconf = unistd::sysconf(unistd::SysconfVar::PAGE_SIZE)
+            .ok()
+            .flatten();
+        checked_page_size(sysconf, kernel_page_size)
+    })
+}
+
+/// Use the sysconf page size if it's plausible, as some libcs (musl) have been known to
+/// get it wrong. Otherwise fall back to `fallback` and then 4096.
+fn checked_page_size(sysconf: Option<i64>, fallback: impl FnOnce() -> Option<usize>) -> usize {
+    if let Some(size) = sysconf
+        .and_then(|size| usize::try_from(size).ok())
+        .filter(|size| PLAUSIBLE_PAGE_SIZES.contains(size))
+    {
+        return size;
+    }
+
+    match fallback().filter(|size| PLAUSIBLE_PAGE_SIZES.contains(size)) {
+        Some(size) => size,
+        None => {
+            eprintln!(
+                "Warning: sysconf gave an implausible page size ({:?}), assuming 4096",
+                sysconf
+            );
+            4096
+        }
+    }
+}
+
+/// The KernelPageSize of the first map in our own smaps, in bytes.
+fn kernel_page_size() -> Option<usize> {
+    let smaps = fs::read_to_string("/proc/self/smaps").ok()?;
+    smaps
+        .lines()
+        .find_map(|line| line.strip_prefix("KernelPageSize:"))
+        .and_then(|size| size.trim().strip_suffix("kB"))
+        .and_then(|size| size.trim().parse::<usize>().ok())
+        .map(|kb| kb * 1024)
+}
+
+/// The path of a file for the process under `proc_root`, which is normally `/proc`.
+pub fn proc_path(proc_root: &Path, pid: usize, file: &str) -> PathBuf {
+    proc_root.join(pid.to_string()).join(file)
+}
+
+pub fn exists(proc_root: &Path, pid: usize) -> bool {
+    proc_root.join(pid.to_string()).exists()
+}
+
+/// The PIDs of every process under `proc_root`.
+pub fn pids(proc_root: &Path) -> io::Result<Vec<usize>> {
+    let mut pids = fs::read_dir(proc_root)?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<_>>();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// The parent PID from the contents of `/proc/<pid>/stat`. The comm in brackets can
+/// have spaces and brackets of its own, so the fields are counted from the last `)`.
+fn parse_ppid(stat: &str) -> Option<usize> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    // Then the state and the parent PID.
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// The `roots` along with all their children, their children's children and so on.
+pub fn tree(proc_root: &Path, roots: &[usize]) -> io::Result<Vec<usize>> {
+    let parents = pids(proc_root)?
+        .into_iter()
+        .filter_map(|pid| {
+            let stat = fs::read_to_string(proc_path(proc_root, pid, "stat")).ok()?;
+            Some((pid, parse_ppid(&stat)?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut tree = roots.iter().copied().collect::<BTreeSet<_>>();
+    // Children usually have higher PIDs than their parents but wrapped PIDs don't, so
+    // go round until nothing more is found.
+    loop {
+        let before = tree.len();
+        for &(pid, ppid) in &parents {
+            if tree.contains(&ppid) {
+                tree.insert(pid);
+            }
+        }
+        if tree.len() == before {
+            return Ok(tree.into_iter().collect());
+        }
+    }
+}
+
+/// The PIDs of the processes in the cgroup at `cgroup`, a directory under
+/// `/sys/fs/cgroup`, not counting those in cgroups below it.
+pub fn cgroup_pids(cgroup: &Path) -> io::Result<Vec<usize>> {
+    parse_cgroup_procs(&fs::read_to_string(cgroup.join("cgroup.procs"))?)
+}
+
+fn parse_cgroup_procs(contents: &str) -> io::Result<Vec<usize>> {
+    let mut pids = contents
+        .lines()
+        .map(|line| {
+            line.trim().parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("not a PID: {}", line))
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// The command name of the process from `/proc/<pid>/comm`.
+pub fn comm(proc_root: &Path, pid: usize) -> io::Result<String> {
+    Ok(fs::read_to_string(proc_path(proc_root, pid, "comm"))?
+        .trim_end()
+        .to_string())
+}
+
+/// Who a process is, so readings of it make sense later on.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub comm: String,
+    /// Empty for kernel threads.
+    pub cmdline: Vec<String>,
+    /// Where `/proc/<pid>/exe` points, if we're allowed to look.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub exe: Option<PathBuf>,
+}
+
+impl Display for ProcessInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PID {} ({})", self.pid, self.comm)?;
+        if !self.cmdline.is_empty() {
+            write!(f, " {}", self.cmdline.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_process_info(proc_root: &Path, pid: usize) -> io::Result<ProcessInfo> {
+    Ok(ProcessInfo {
+        pid,
+        comm: comm(proc_root, pid)?,
+        cmdline: parse_cmdline(&fs::read(proc_path(proc_root, pid, "cmdline"))?),
+        exe: fs::read_link(proc_path(proc_root, pid, "exe")).ok(),
+    })
+}
+
+/// The release of the running kernel, eg. `6.8.0-45-generic`.
+#[cfg(feature = "serde")]
+pub fn kernel_release(proc_root: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(proc_root.join("sys/kernel/osrelease"))?
+        .trim_end()
+        .to_string())
+}
+
+/// Whether the process goes by `name`, either as its comm or the file name of the
+/// program it was started as. The comm is cut short at 15 characters, so a long name
+/// only matches the command line.
+fn is_named(info: &ProcessInfo, name: &str) -> bool {
+    info.comm == name
+        || info
+            .cmdline
+            .first()
+            .and_then(|program| Path::new(program).file_name())
+            .is_some_and(|program| program == name)
+}
+
+/// The PIDs of the processes going by `name`, other than this one.
+pub fn pids_named(proc_root: &Path, name: &str) -> io::Result<Vec<usize>> {
+    let own = std::process::id() as usize;
+    Ok(pids(proc_root)?
+        .into_iter()
+        .filter(|&pid| pid != own)
+        .filter(|&pid| read_process_info(proc_root, pid).is_ok_and(|info| is_named(&info, name)))
+        .collect())
+}
+
+/// The arguments in `/proc/<pid>/cmdline` are each terminated by a NUL.
+fn parse_cmdline(contents: &[u8]) -> Vec<String> {
+    contents
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+pub fn read_statm(proc_root: &Path, pid: usize) -> io::Result<Statm> {
+    parse_statm(&fs::read_to_string(proc_path(proc_root, pid, "statm"))?)
+}
+
+fn parse_statm(contents: &str) -> io::Result<Statm> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unexpected /proc/[pid]/statm format",
+        )
+    };
+
+    // The fields are size, resident, shared, text, lib (unused), data and dt (unused).
+    let parts = contents
+        .split_whitespace()
+        .map(|part| part.parse::<usize>().map_err(|_| invalid()))
+        .collect::<io::Result<Vec<_>>>()?;
+    if parts.len() < 6 {
+        return Err(invalid());
+    }
+
+    Ok(Statm {
+        size: parts[0],
+        resident: parts[1],
+        shared: parts[2],
+        text: parts[3],
+        data: parts[5],
+    })
+}
+
+/// The resident set size of the process in bytes.
+pub fn get_rss(proc_root: &Path, pid: usize) -> io::Result<usize> {
+    read_statm(proc_root, pid).map(|statm| statm.rss())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(
+            vec!["sleep".to_string(), "100".to_string()],
+            parse_cmdline(b"sleep\x00100\x00")
+        );
+        assert_eq!(Vec::<String>::new(), parse_cmdline(b""));
+    }
+
+    #[test]
+    fn test_parse_cgroup_procs() {
+        assert_eq!(vec![12, 345], parse_cgroup_procs("345\n12\n").unwrap());
+        assert_eq!(Vec::<usize>::new(), parse_cgroup_procs("").unwrap());
+        assert!(parse_cgroup_procs("12\nx\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_ppid() {
+        assert_eq!(
+            Some(1),
+            parse_ppid("1234 (sleep) S 1 1234 1234 0 -1 4194304")
+        );
+        assert_eq!(
+            Some(77),
+            parse_ppid("1234 (a) b (c)) R 77 1234 1234 0 -1 4194304")
+        );
+        assert_eq!(None, parse_ppid("1234 (sleep"));
+    }
+
+    #[test]
+    fn test_is_named() {
+        let info = ProcessInfo {
+            pid: 1,
+            comm: "nginx".to_string(),
+            cmdline: vec!["/usr/sbin/nginx".to_string(), "-g".to_string()],
+            exe: None,
+        };
+        assert!(is_named(&info, "nginx"));
+
+        let info = ProcessInfo {
+            comm: "very-long-daemo".to_string(),
+            cmdline: vec!["/opt/very-long-daemon-name".to_string()],
+            ..info
+        };
+        assert!(is_named(&info, "very-long-daemon-name"));
+        assert!(!is_named(&info, "very-long"));
+    }
+
+    #[test]
+    fn test_proc_path() {
+        assert_eq!(
+            Path::new("/host/proc/7/smaps"),
+            proc_path(Path::new("/host/proc"), 7, "smaps")
+        );
+
+        let root = Scratch::new("proc-root");
+        fs::create_dir(root.join("7")).unwrap();
+        assert!(exists(root.path(), 7));
+        assert!(!exists(root.path(), 8));
+    }
+
+    #[test]
+    fn test_read_statm() {
+        let root = Scratch::new("statm");
+        fs::create_dir(root.join("42")).unwrap();
+        fs::write(root.join("42/statm"), "2048 300 100 20 0 500 0\n").unwrap();
+        assert_eq!(
+            Statm {
+                size: 2048,
+                resident: 300,
+                shared: 100,
+                text: 20,
+                data: 500,
+            },
+            read_statm(root.path(), 42).unwrap()
+        );
+        assert_eq!(300 * page_size(), get_rss(root.path(), 42).unwrap());
+
+        fs::write(root.join("42/statm"), "2048 300\n").unwrap();
+        assert!(read_statm(root.path(), 42).is_err());
+        assert!(read_statm(root.path(), 43).is_err());
+    }
+
+    #[test]
+    fn test_checked_page_size() {
+        assert_eq!(16384, checked_page_size(Some(16384), || Some(4096)));
+        assert_eq!(8192, checked_page_size(Some(0), || Some(8192)));
+        assert_eq!(8192, checked_page_size(Some(1 << 40), || Some(8192)));
+        assert_eq!(4096, checked_page_size(Some(-1), || None));
+        assert_eq!(4096, checked_page_size(None, || Some(3)));
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+/// A percentage counter on stderr for parses that take long enough to need one.
+pub struct Progress {
+    total: usize,
+    percent: Option<usize>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            percent: None,
+        }
+    }
+
+    /// Redraw the counter, only touching the terminal when the percentage moves on.
+    pub fn update(&mut self, consumed: usize) {
+        let percent = consumed * 100 / self.total.max(1);
+        if self.percent != Some(percent) {
+            self.percent = Some(percent);
+            eprint!(
+                "\rParsing {:>3}% ({} / {} MB)",
+                percent,
+                consumed / (1024 * 1024),
+                self.total / (1024 * 1024)
+            );
+            let _ = io::stderr().flush();
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.percent.is_some() {
+            eprintln!();
+            self.percent = None;
+        }
+    }
+}
+
+/// Parses that stop early, such as with `--limit`, never see the end of the input.
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
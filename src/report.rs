@@ -0,0 +1,464 @@
+//! The alternative views of a single reading, in place of listing every map.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Write},
+};
+
+use crate::{memory_map::MemoryMap, template, totals, units::Units};
+
+pub fn segment_counts(mut writer: impl Write, memory_map: &[MemoryMap]) -> io::Result<()> {
+    let mut counts = totals::segment_counts(memory_map)
+        .into_iter()
+        .collect::<Vec<_>>();
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (path, count) in counts {
+        writeln!(writer, "{:>4} {}", count, path)?;
+    }
+    Ok(())
+}
+
+/// Rss and Pss split into broad categories such as code, heap and stack, biggest Rss
+/// first.
+pub fn by_category(writer: impl Write, memory_map: &[MemoryMap], units: Units) -> io::Result<()> {
+    grouped(writer, "CATEGORY", memory_map, MemoryMap::category, units)
+}
+
+/// Rss and Pss for each kind of backing, such as file, memfd or shm, biggest Rss first.
+pub fn by_kind(writer: impl Write, memory_map: &[MemoryMap], units: Units) -> io::Result<()> {
+    grouped(writer, "KIND", memory_map, MemoryMap::kind, units)
+}
+
+fn grouped<K: Ord + Display>(
+    mut writer: impl Write,
+    heading: &str,
+    memory_map: &[MemoryMap],
+    key: impl Fn(&MemoryMap) -> K,
+    units: Units,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:<16} {:>5} {:>14} {:>6} {:>14}",
+        heading, "MAPS", "RSS", "RSS%", "PSS"
+    )?;
+
+    let total = totals::totals(memory_map);
+    let mut groups = totals::group_by(memory_map, key)
+        .into_iter()
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.rss));
+    for (group, totals) in groups {
+        writeln!(
+            writer,
+            "{:<16} {:>5} {:>14} {:>5.1}% {:>14}",
+            group,
+            totals.maps,
+            units.format(totals.rss),
+            totals.rss as f64 * 100.0 / total.rss.max(1) as f64,
+            units.format(totals.pss)
+        )?;
+    }
+    Ok(())
+}
+
+/// The `--fields` of each map as columns under their names, with the numbers lined up
+/// on the right.
+pub fn fields(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    fields: &template::Fields,
+    units: Units,
+) -> io::Result<()> {
+    let rows = memory_map
+        .iter()
+        .map(|m| fields.values(m, units))
+        .collect::<Vec<_>>();
+    let names = fields.names().collect::<Vec<_>>();
+    let widths = names
+        .iter()
+        .enumerate()
+        .map(|(column, name)| {
+            rows.iter()
+                .map(|row| row[column].to_string().len())
+                .fold(name.len(), usize::max)
+        })
+        .collect::<Vec<_>>();
+    let is_number = |column: usize| {
+        rows.first()
+            .is_some_and(|row| matches!(row[column], template::Value::Number(_)))
+    };
+
+    let mut line = |cells: Vec<String>| -> io::Result<()> {
+        let cells = cells
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| match is_number(column) {
+                true => format!("{:>width$}", cell, width = widths[column]),
+                false => format!("{:<width$}", cell, width = widths[column]),
+            })
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}", cells.join("  ").trim_end())
+    };
+    line(names.iter().map(|name| name.to_uppercase()).collect())?;
+    for row in &rows {
+        line(row.iter().map(|value| value.to_string()).collect())?;
+    }
+    Ok(())
+}
+
+/// Every map of the same path rolled up into one line, ordered by the sum of `sort`
+/// over its maps, biggest first unless `reverse`. With `top` only that many files are
+/// listed, followed by the rest added up. Each file's share of the Pss of all the maps,
+/// anonymous ones included, is shown along with the running share down the list.
+pub fn files(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    sort: impl Fn(&MemoryMap) -> usize,
+    reverse: bool,
+    top: Option<usize>,
+    units: Units,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:>5} {:>14} {:>14} {:>14} {:>6} {:>6}  PATH",
+        "MAPS", "SIZE", "RSS", "PSS", "PSS%", "CUM%"
+    )?;
+
+    let mut sums = BTreeMap::<&str, usize>::new();
+    for m in memory_map {
+        if let Some(path) = &m.path {
+            *sums.entry(path).or_default() += sort(m);
+        }
+    }
+    let mut files = totals::group_by(memory_map, |m| m.path.clone())
+        .into_iter()
+        .filter_map(|(path, totals)| Some((path?, totals)))
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(path, _)| std::cmp::Reverse(sums[path.as_str()]));
+    if reverse {
+        files.reverse();
+    }
+    let rest = files.split_off(top.unwrap_or(files.len()).min(files.len()));
+    if !rest.is_empty() {
+        let mut everything_else = totals::Totals::default();
+        for (_, totals) in &rest {
+            everything_else.maps += totals.maps;
+            everything_else.size += totals.size;
+            everything_else.rss += totals.rss;
+            everything_else.pss += totals.pss;
+        }
+        files.push((format!("({} other files)", rest.len()), everything_else));
+    }
+    let mut shares = totals::Shares::new(totals::totals(memory_map).pss);
+    for (path, totals) in files {
+        writeln!(
+            writer,
+            "{:>5} {:>14} {:>14} {:>14} {}  {}",
+            totals.maps,
+            units.format(totals.size),
+            units.format(totals.rss),
+            units.format(totals.pss),
+            shares.next(totals.pss),
+            path
+        )?;
+    }
+    Ok(())
+}
+
+/// Rss and Pss of each file by device and inode, biggest Rss first. The path shown is
+/// the first one the file was mapped under.
+pub fn by_inode(mut writer: impl Write, memory_map: &[MemoryMap], units: Units) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:>8} {:>10} {:>5} {:>14} {:>14}  PATH",
+        "DEVICE", "INODE", "MAPS", "RSS", "PSS"
+    )?;
+
+    let mut groups = totals::group_by_inode(memory_map)
+        .into_iter()
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.rss));
+    for ((device, inode), totals) in groups {
+        let path = memory_map
+            .iter()
+            .find(|m| m.device == device && m.inode == inode)
+            .and_then(|m| m.path.as_deref())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            "{:>8} {:>10} {:>5} {:>14} {:>14}  {}",
+            device,
+            inode,
+            totals.maps,
+            units.format(totals.rss),
+            units.format(totals.pss),
+            path
+        )?;
+    }
+    Ok(())
+}
+
+/// The estimated Pss of each map next to its Rss, for kernels without a Pss field.
+pub fn pss_adjust(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    share_count: usize,
+    units: Units,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "Estimated Pss assumes every shared page is mapped by {} processes, it is only an approximation",
+        share_count
+    )?;
+    writeln!(writer, "{:>14} {:>14}  MAP", "RSS", "EST PSS")?;
+
+    let mut rss = 0;
+    let mut estimated = 0;
+    for m in memory_map {
+        let pss = m.estimated_pss(share_count);
+        rss += m.rss().unwrap_or_default();
+        estimated += pss;
+        writeln!(
+            writer,
+            "{:>14} {:>14}  {} {} {}",
+            units.format(m.rss().unwrap_or_default()),
+            units.format(pss),
+            m.address_range,
+            m.permissions,
+            m.path.as_deref().unwrap_or_default()
+        )?;
+    }
+
+    writeln!(
+        writer,
+        "{:>14} {:>14}  total",
+        units.format(rss),
+        units.format(estimated)
+    )?;
+    Ok(())
+}
+
+/// The unmapped holes between maps, and how much of the span from the lowest to the
+/// highest mapped address they take up. Maps must be in address order. `[vsyscall]`
+/// and anything else above user space is left out, or it would swamp the span.
+pub fn gaps(mut writer: impl Write, memory_map: &[MemoryMap], units: Units) -> io::Result<()> {
+    writeln!(writer, "{:<33} {:>14}", "GAP", "SIZE")?;
+
+    let gaps = totals::gaps(memory_map);
+    for gap in &gaps {
+        writeln!(
+            writer,
+            "{:<33} {:>14}",
+            gap.to_string(),
+            units.format(gap.len() / 1024)
+        )?;
+    }
+
+    let unmapped = gaps.iter().map(|gap| gap.len()).sum::<usize>();
+    let user = memory_map
+        .iter()
+        .filter(|m| totals::in_user_space(m))
+        .collect::<Vec<_>>();
+    let span = match (user.first(), user.last()) {
+        (Some(first), Some(last)) => last
+            .address_range
+            .to
+            .saturating_sub(first.address_range.from),
+        _ => 0,
+    };
+    writeln!(
+        writer,
+        "{} gaps, {} unmapped of the {} from the lowest to the highest map",
+        gaps.len(),
+        units.format(unmapped / 1024),
+        units.format(span / 1024)
+    )?;
+    Ok(())
+}
+
+/// Swap next to SwapPss for every map with anything swapped out. Swap counts shared
+/// pages in full for every process whereas SwapPss splits them, which explains why
+/// the two disagree with each other and with `free`.
+pub fn swap(mut writer: impl Write, memory_map: &[MemoryMap], units: Units) -> io::Result<()> {
+    writeln!(writer, "{:>14} {:>14}  MAP", "SWAP", "SWAP PSS")?;
+
+    let swapped = memory_map
+        .iter()
+        .filter(|m| m.swap().unwrap_or_default() > 0)
+        .collect::<Vec<_>>();
+    for m in &swapped {
+        writeln!(
+            writer,
+            "{:>14} {:>14}  {} {} {}",
+            units.format(m.swap().unwrap_or_default()),
+            units.format(m.swap_pss().unwrap_or_default()),
+            m.address_range,
+            m.permissions,
+            m.path.as_deref().unwrap_or_default()
+        )?;
+    }
+
+    let totals = totals::totals(swapped);
+    writeln!(
+        writer,
+        "{:>14} {:>14}  total",
+        units.format(totals.swap),
+        units.format(totals.swap_pss)
+    )?;
+    Ok(())
+}
+
+/// Folded stacks for `flamegraph.pl`, one frame per path component, eg.
+/// `usr;lib;libc.so.6 1412`, weighted by the size `weight` gives in kB. Anonymous maps
+/// are all under an `[anonymous]` frame.
+pub fn folded(
+    mut writer: impl Write,
+    memory_map: &[MemoryMap],
+    weight: impl Fn(&MemoryMap) -> usize,
+    units: Units,
+) -> io::Result<()> {
+    let mut stacks = BTreeMap::<String, usize>::new();
+    for m in memory_map {
+        let stack = match &m.path {
+            Some(path) => path.trim_start_matches('/').replace('/', ";"),
+            None => "[anonymous]".to_string(),
+        };
+        *stacks.entry(stack).or_default() += weight(m);
+    }
+
+    for (stack, size) in stacks {
+        if size > 0 {
+            writeln!(writer, "{} {}", stack, units.value(size))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps() -> Vec<MemoryMap> {
+        [
+            "1000-3000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\nSize: 8 kB\nRss: 8 kB\nPss: 4 kB\n",
+            "3000-4000 r--p 00002000 fe:00 12 /usr/lib/libc.so.6\nSize: 4 kB\nRss: 4 kB\nPss: 2 kB\n",
+            "6000-8000 rw-p 00000000 00:00 0\nSize: 8 kB\nRss: 4 kB\nPss: 4 kB\nSwap: 4 kB\nSwapPss: 2 kB\n",
+            "9000-a000 rw-p 00000000 00:00 0\nSize: 4 kB\nRss: 0 kB\nPss: 0 kB\n",
+            "ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0 [vsyscall]\nSize: 4 kB\n",
+        ]
+        .iter()
+        .map(|m| m.parse().unwrap())
+        .collect()
+    }
+
+    /// What `report` writes, as lines.
+    fn lines(report: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Vec<String> {
+        let mut out = Vec::new();
+        report(&mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_files() {
+        let maps = maps();
+        let rss = |m: &MemoryMap| m.rss().unwrap_or_default();
+        assert_eq!(
+            vec![
+                " MAPS           SIZE            RSS            PSS   PSS%   CUM%  PATH",
+                "    2          12 kB          12 kB           6 kB  60.0%  60.0%  /usr/lib/libc.so.6",
+                "    1           4 kB           0 kB           0 kB   0.0%  60.0%  [vsyscall]",
+            ],
+            lines(|out| files(out, &maps, rss, false, None, Units::Kb))
+        );
+        assert_eq!(
+            "    3          16 kB          12 kB           6 kB  60.0%  60.0%  (2 other files)",
+            lines(|out| files(out, &maps, rss, false, Some(0), Units::Kb))[1]
+        );
+        assert_eq!(
+            "[vsyscall]",
+            lines(|out| files(out, &maps, rss, true, None, Units::Kb))[1]
+                .rsplit("  ")
+                .next()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_segment_counts() {
+        assert_eq!(
+            vec!["   2 /usr/lib/libc.so.6", "   1 [vsyscall]"],
+            lines(|out| segment_counts(out, &maps()))
+        );
+    }
+
+    #[test]
+    fn test_by_inode() {
+        assert_eq!(
+            vec![
+                "  DEVICE      INODE  MAPS            RSS            PSS  PATH",
+                "   fe:00         12     2          12 kB           6 kB  /usr/lib/libc.so.6",
+            ],
+            lines(|out| by_inode(out, &maps(), Units::Kb))
+        );
+    }
+
+    #[test]
+    fn test_gaps() {
+        assert_eq!(
+            vec![
+                "GAP                                         SIZE",
+                "00004000-00006000                           8 kB",
+                "00008000-00009000                           4 kB",
+                "2 gaps, 12 kB unmapped of the 36 kB from the lowest to the highest map",
+            ],
+            lines(|out| gaps(out, &maps(), Units::Kb))
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        assert_eq!(
+            vec![
+                "          SWAP       SWAP PSS  MAP",
+                "          4 kB           2 kB  00006000-00008000 rw-p ",
+                "          4 kB           2 kB  total",
+            ],
+            lines(|out| swap(out, &maps(), Units::Kb))
+        );
+    }
+
+    #[test]
+    fn test_folded() {
+        // Both anonymous maps go under one frame, and [vsyscall] has no Rss to show.
+        assert_eq!(
+            vec!["[anonymous] 4", "usr;lib;libc.so.6 12"],
+            lines(|out| folded(out, &maps(), |m| m.rss().unwrap_or_default(), Units::Kb))
+        );
+        assert_eq!(
+            vec!["[anonymous] 4096", "usr;lib;libc.so.6 12288"],
+            lines(|out| folded(out, &maps(), |m| m.rss().unwrap_or_default(), Units::Bytes))
+        );
+    }
+
+    #[test]
+    fn test_pss_adjust() {
+        let maps = [
+            "1000-3000 rw-s 00000000 00:05 7 /dev/shm/queue\nRss: 8 kB\nShared_Dirty: 8 kB\n",
+            "3000-4000 rw-p 00000000 00:00 0\nRss: 4 kB\nPrivate_Dirty: 4 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        assert_eq!(
+            vec![
+                "          8 kB           4 kB  00001000-00003000 rw-s /dev/shm/queue",
+                "          4 kB           4 kB  00003000-00004000 rw-p ",
+                "         12 kB           8 kB  total",
+            ],
+            lines(|out| pss_adjust(out, &maps, 2, Units::Kb))[2..]
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Scratch directories for the tests that need files on disk.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Counts the directories made, so no two tests in a run share one.
+static MADE: AtomicUsize = AtomicUsize::new(0);
+
+/// An empty directory of a test's own, removed when it's dropped even if the test panics.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// `name` only makes the directory easier to recognise.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "shmaps-test-{}-{}-{}",
+            std::process::id(),
+            MADE.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scratch() {
+        let (first, second) = (Scratch::new("same"), Scratch::new("same"));
+        assert_ne!(first.path(), second.path());
+
+        let path = first.path().to_path_buf();
+        fs::write(first.join("file"), "contents").unwrap();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,197 @@
+//! Whole readings saved as JSON or MessagePack, which can be read back in place of a
+//! smaps capture.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::{memory_map::MemoryMap, process::ProcessInfo};
+
+/// One reading of a session saved by `shmaps record`, written a line of JSON each.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub timestamp: DateTime<FixedOffset>,
+    /// The process the session is of, if it could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<ProcessInfo>,
+    pub maps: Vec<MemoryMap>,
+}
+
+/// A single reading saved by `shmaps snapshot`, with enough about where it came from
+/// to make sense of it once the process is long gone.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub timestamp: DateTime<FixedOffset>,
+    pub process: ProcessInfo,
+    /// The release of the kernel the reading was taken on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    /// The Rss from `/proc/<pid>/statm` in kB, to check the maps against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statm_rss_kb: Option<usize>,
+    pub maps: Vec<MemoryMap>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Json,
+    Msgpack,
+}
+
+impl SnapshotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Json => "json",
+            SnapshotFormat::Msgpack => "msgpack",
+        }
+    }
+
+    /// Work out the format from the extension, or failing that the first byte. A
+    /// snapshot is an array, or an object if saved by `shmaps snapshot`, so JSON starts
+    /// with `[` or `{` and MessagePack with an array marker, none of which can start a
+    /// smaps capture.
+    fn detect(path: &Path, first: Option<u8>) -> Option<SnapshotFormat> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => return Some(SnapshotFormat::Json),
+            Some("msgpack" | "mpk") => return Some(SnapshotFormat::Msgpack),
+            _ => {}
+        }
+
+        match first? {
+            b'[' | b'{' => Some(SnapshotFormat::Json),
+            0x90..=0x9f | 0xdc | 0xdd => Some(SnapshotFormat::Msgpack),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+pub fn write(
+    writer: impl Write,
+    memory_map: &[MemoryMap],
+    format: SnapshotFormat,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    match format {
+        SnapshotFormat::Json => serde_json::to_writer(&mut writer, memory_map)?,
+        SnapshotFormat::Msgpack => {
+            rmp_serde::encode::write(&mut writer, memory_map).map_err(invalid)?
+        }
+    }
+    writer.flush()
+}
+
+/// Load the file if it's a snapshot, or `None` if it looks like a smaps capture. Only
+/// the maps are kept from one saved with metadata by `shmaps snapshot`.
+pub fn load(path: &Path) -> io::Result<Option<Vec<MemoryMap>>> {
+    let mut first = [0];
+    let read = fs::File::open(path)?.read(&mut first)?;
+    let Some(format) = SnapshotFormat::detect(path, (read > 0).then_some(first[0])) else {
+        return Ok(None);
+    };
+
+    let contents = fs::read(path)?;
+    let memory_map = match format {
+        SnapshotFormat::Json if contents.trim_ascii_start().starts_with(b"{") => {
+            serde_json::from_slice::<Snapshot>(&contents)?.maps
+        }
+        SnapshotFormat::Json => serde_json::from_slice(&contents)?,
+        SnapshotFormat::Msgpack => rmp_serde::from_slice(&contents).map_err(invalid)?,
+    };
+    Ok(Some(memory_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn test_round_trip() {
+        let memory_map = vec!["1000-2000 rw-p 00000000 00:00 0 
+Size:                  4 kB
+Rss:                   4 kB
+ProtectionKey:         0
+VmFlags: rd wr
+"
+        .parse::<MemoryMap>()
+        .unwrap()];
+
+        let dir = Scratch::new("snapshot");
+        for format in [SnapshotFormat::Json, SnapshotFormat::Msgpack] {
+            let path = dir.join(format!("snapshot.{}", format.extension()));
+            write(fs::File::create(&path).unwrap(), &memory_map, format).unwrap();
+            let loaded = load(&path).unwrap();
+
+            assert_eq!(Some(memory_map.clone()), loaded);
+        }
+    }
+
+    #[test]
+    fn test_recorded_round_trip() {
+        let recorded = Recorded {
+            timestamp: DateTime::parse_from_rfc3339("2024-06-01T12:00:00.5+01:00").unwrap(),
+            process: None,
+            maps: vec![
+                "1000-2000 rw-p 00000000 00:00 0 \nRss: 4 kB\nVmFlags: rd wr\n"
+                    .parse()
+                    .unwrap(),
+            ],
+        };
+
+        let line = serde_json::to_string(&recorded).unwrap();
+        assert!(line.starts_with(r#"{"timestamp":"2024-06-01T12:00:00.500+01:00","#));
+        assert_eq!(recorded, serde_json::from_str(&line).unwrap());
+    }
+
+    #[test]
+    fn test_load_with_metadata() {
+        let snapshot = Snapshot {
+            timestamp: DateTime::parse_from_rfc3339("2024-06-01T12:00:00+01:00").unwrap(),
+            process: ProcessInfo {
+                pid: 42,
+                comm: "sleep".to_string(),
+                cmdline: vec!["sleep".to_string(), "100".to_string()],
+                exe: Some("/usr/bin/sleep".into()),
+            },
+            kernel: Some("6.8.0".to_string()),
+            statm_rss_kb: Some(4),
+            maps: vec![
+                "1000-2000 rw-p 00000000 00:00 0 \nRss: 4 kB\nVmFlags: rd wr\n"
+                    .parse()
+                    .unwrap(),
+            ],
+        };
+
+        // No extension, so it has to be told from the `{`.
+        let dir = Scratch::new("meta");
+        let path = dir.join("snapshot");
+        fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        let loaded = load(&path).unwrap();
+
+        assert_eq!(Some(snapshot.maps), loaded);
+    }
+
+    #[test]
+    fn test_detect() {
+        let path = Path::new("capture");
+        assert_eq!(
+            Some(SnapshotFormat::Json),
+            SnapshotFormat::detect(path, Some(b'['))
+        );
+        assert_eq!(
+            Some(SnapshotFormat::Msgpack),
+            SnapshotFormat::detect(path, Some(0x93))
+        );
+        assert_eq!(None, SnapshotFormat::detect(path, Some(b'7')));
+        assert_eq!(None, SnapshotFormat::detect(path, None));
+    }
+}
@@ -0,0 +1,90 @@
+//! `--sparkline`, the total Rss, Pss and Uss over the last few readings of a watch drawn
+//! with block characters, eg. `▁▁▂▄▇█`.
+
+use std::collections::VecDeque;
+
+use crate::{totals::Totals, units::Units};
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draw the values scaled between the smallest and biggest of them, so small changes
+/// in a big process still show. A flat line is drawn at the bottom.
+fn render(values: impl Iterator<Item = usize> + Clone) -> String {
+    let min = values.clone().min().unwrap_or_default();
+    let max = values.clone().max().unwrap_or_default();
+    values
+        .map(|value| {
+            let level = match max - min {
+                0 => 0,
+                range => (value - min) * (BLOCKS.len() - 1) / range,
+            };
+            BLOCKS[level]
+        })
+        .collect()
+}
+
+/// The totals of the last `width` readings.
+pub struct Sparklines {
+    width: usize,
+    readings: VecDeque<(usize, usize, usize)>,
+}
+
+impl Sparklines {
+    pub fn new(width: usize) -> Self {
+        Sparklines {
+            width,
+            readings: VecDeque::with_capacity(width),
+        }
+    }
+
+    pub fn record(&mut self, totals: &Totals) {
+        if self.readings.len() == self.width {
+            self.readings.pop_front();
+        }
+        self.readings
+            .push_back((totals.rss, totals.pss, totals.uss));
+    }
+
+    /// A line each for Rss, Pss and Uss, ending with the latest value.
+    pub fn format(&self, units: Units) -> String {
+        let (rss, pss, uss) = self.readings.back().copied().unwrap_or_default();
+        format!(
+            "RSS {} {}\nPSS {} {}\nUSS {} {}",
+            render(self.readings.iter().map(|(rss, _, _)| *rss)),
+            units.format(rss),
+            render(self.readings.iter().map(|(_, pss, _)| *pss)),
+            units.format(pss),
+            render(self.readings.iter().map(|(_, _, uss)| *uss)),
+            units.format(uss)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!("▁▄█▁", render([100, 150, 200, 100].into_iter()));
+        assert_eq!("▁▁▁", render([5, 5, 5].into_iter()));
+        assert_eq!("", render(std::iter::empty()));
+    }
+
+    #[test]
+    fn test_sparklines() {
+        let mut sparklines = Sparklines::new(3);
+        for (rss, pss, uss) in [(1, 1, 1), (10, 5, 2), (20, 5, 2), (30, 9, 2)] {
+            sparklines.record(&Totals {
+                rss,
+                pss,
+                uss,
+                ..Totals::default()
+            });
+        }
+        assert_eq!(
+            "RSS ▁▄█ 30 kB\nPSS ▁▁█ 9 kB\nUSS ▁▁▁ 2 kB",
+            sparklines.format(Units::Kb)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! `--record`, every reading of a watch saved to an SQLite database so it can be queried
+//! afterwards. Each reading is a row of `readings`, and each of its maps a row of `maps`
+//! pointing back at it, with the same sizes as `--format csv` in kB.
+
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+use rusqlite::{params_from_iter, types::Value, Connection};
+
+use crate::{csv::SIZE_COLUMNS, memory_map::MemoryMap};
+
+pub struct Database {
+    connection: Connection,
+    insert_map: String,
+}
+
+impl Database {
+    /// Open the database, creating the tables if they aren't there yet, so a session can
+    /// carry on one that was recorded before.
+    pub fn open(path: &Path) -> rusqlite::Result<Database> {
+        let connection = Connection::open(path)?;
+
+        let sizes = SIZE_COLUMNS
+            .iter()
+            .map(|(column, _)| format!(", {}_kb INTEGER", column))
+            .collect::<String>();
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS readings (
+                id INTEGER PRIMARY KEY,
+                timestamp TEXT NOT NULL,
+                pid INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS maps (
+                reading INTEGER NOT NULL REFERENCES readings (id),
+                start TEXT NOT NULL,
+                end TEXT NOT NULL,
+                perms TEXT NOT NULL,
+                offset INTEGER NOT NULL,
+                device TEXT NOT NULL,
+                inode INTEGER NOT NULL,
+                path TEXT,
+                deleted INTEGER NOT NULL{}
+            );
+            CREATE INDEX IF NOT EXISTS maps_reading ON maps (reading);",
+            sizes
+        ))?;
+
+        let columns = SIZE_COLUMNS
+            .iter()
+            .map(|(column, _)| format!(", {}_kb", column))
+            .collect::<String>();
+        let placeholders = ", ?".repeat(9 + SIZE_COLUMNS.len());
+        let insert_map = format!(
+            "INSERT INTO maps (reading, start, end, perms, offset, device, inode, path, deleted{}) \
+             VALUES ({})",
+            columns,
+            &placeholders[2..]
+        );
+
+        Ok(Database {
+            connection,
+            insert_map,
+        })
+    }
+
+    /// Save a reading in one transaction, so a session that's killed part way through
+    /// never leaves half of one behind.
+    pub fn record(
+        &mut self,
+        timestamp: DateTime<FixedOffset>,
+        pid: Option<usize>,
+        memory_map: &[MemoryMap],
+    ) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO readings (timestamp, pid) VALUES (?1, ?2)",
+            (timestamp.to_rfc3339(), pid.map(|pid| pid as i64)),
+        )?;
+        let reading = transaction.last_insert_rowid();
+
+        {
+            let mut insert = transaction.prepare_cached(&self.insert_map)?;
+            for m in memory_map {
+                // Addresses are hex text padded to the same width, as the top of the
+                // address space doesn't fit in SQLite's signed integers and this still
+                // sorts in address order.
+                let mut row = vec![
+                    Value::Integer(reading),
+                    Value::Text(format!("{:016x}", m.address_range.from)),
+                    Value::Text(format!("{:016x}", m.address_range.to)),
+                    Value::Text(m.permissions.to_string()),
+                    Value::Integer(m.offset as i64),
+                    Value::Text(m.device.to_string()),
+                    Value::Integer(m.inode as i64),
+                    m.path.clone().map_or(Value::Null, Value::Text),
+                    Value::Integer(m.deleted.into()),
+                ];
+                row.extend(SIZE_COLUMNS.iter().map(|(_, field)| {
+                    m.sizes
+                        .get(field)
+                        .map_or(Value::Null, |size| Value::Integer(size.value() as i64))
+                }));
+                insert.execute(params_from_iter(row))?;
+            }
+        }
+
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let memory_map = [
+            "7f3d2c21b000-7f3d2c21e000 r-xp 00002000 fe:00 1234 /usr/lib/libc.so.6 (deleted)
+Size:                 12 kB
+Rss:                   8 kB
+VmFlags: rd ex
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+            "ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0 [vsyscall]
+Rss:                   0 kB
+VmFlags: ex
+"
+            .parse::<MemoryMap>()
+            .unwrap(),
+        ];
+
+        let mut database = Database::open(Path::new(":memory:")).unwrap();
+        let timestamp = DateTime::parse_from_rfc3339("2024-06-01T12:00:00+01:00").unwrap();
+        database.record(timestamp, Some(42), &memory_map).unwrap();
+        database
+            .record(timestamp, Some(42), &memory_map[..1])
+            .unwrap();
+
+        let rows = database
+            .connection
+            .prepare("SELECT reading, end, path, deleted, size_kb, rss_kb FROM maps ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, bool>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+
+        let libc = (
+            "00007f3d2c21e000".to_string(),
+            Some("/usr/lib/libc.so.6".to_string()),
+            true,
+            Some(12),
+            Some(8),
+        );
+        let vsyscall = (
+            "ffffffffff601000".to_string(),
+            Some("[vsyscall]".to_string()),
+            false,
+            None,
+            Some(0),
+        );
+        let with_reading =
+            |reading, (end, path, deleted, size, rss)| (reading, end, path, deleted, size, rss);
+        assert_eq!(
+            vec![
+                with_reading(1, libc.clone()),
+                with_reading(1, vsyscall),
+                with_reading(2, libc),
+            ],
+            rows
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! `--template`, a small format string filled in from each map, like `find -printf`, and
+//! `--fields`, which picks the values to show by the same names.
+
+use std::{borrow::Cow, fmt::Write, str::FromStr};
+
+#[cfg(feature = "serde")]
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{
+    memory_map::{self, MemoryMap},
+    units::Units,
+};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Field {
+    From,
+    To,
+    Permissions,
+    Offset,
+    Device,
+    Inode,
+    Path,
+    VmFlags,
+    /// Index into `SIZE_FIELDS`.
+    Size(usize),
+    /// Private_Clean and Private_Dirty.
+    Uss,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        let field = match name {
+            "from" => Field::From,
+            "to" => Field::To,
+            "perms" | "permissions" => Field::Permissions,
+            "offset" => Field::Offset,
+            "device" => Field::Device,
+            "inode" => Field::Inode,
+            "path" => Field::Path,
+            "flags" | "vm_flags" => Field::VmFlags,
+            "uss" => Field::Uss,
+            _ => Field::Size(
+                memory_map::SIZE_FIELDS
+                    .iter()
+                    .position(|field| field.eq_ignore_ascii_case(name))?,
+            ),
+        };
+        Some(field)
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Field::From | Field::To | Field::Inode | Field::Size(_) | Field::Uss
+        )
+    }
+
+    fn number(&self, m: &MemoryMap) -> Option<usize> {
+        match self {
+            Field::From => Some(m.address_range.from),
+            Field::To => Some(m.address_range.to),
+            Field::Inode => Some(m.inode as usize),
+            Field::Size(index) => Some(
+                m.sizes
+                    .get(memory_map::SIZE_FIELDS[*index])
+                    .map_or(0, |size| size.value()),
+            ),
+            Field::Uss => Some(m.uss().unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    fn text<'a>(&self, m: &'a MemoryMap) -> Cow<'a, str> {
+        match self {
+            Field::Permissions => Cow::Owned(m.permissions.to_string()),
+            Field::Offset => Cow::Owned(format!("{:08x}", m.offset)),
+            Field::Device => Cow::Owned(m.device.to_string()),
+            Field::Path => Cow::Borrowed(m.path.as_deref().unwrap_or_default()),
+            Field::VmFlags => Cow::Owned(m.vm_flags.to_string()),
+            Field::From | Field::To | Field::Inode | Field::Size(_) | Field::Uss => {
+                Cow::Borrowed("")
+            }
+        }
+    }
+}
+
+/// One value of a map for `--fields`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Value<'a> {
+    Number(usize),
+    Text(Cow<'a, str>),
+}
+
+impl std::fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(number) => number.fmt(f),
+            Value::Text(text) => text.fmt(f),
+        }
+    }
+}
+
+/// A parsed `--fields`, the comma separated names of the values to show for each map,
+/// named as in a template, eg. `rss,pss,swap,path`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Fields(Vec<(String, Field)>);
+
+impl FromStr for Fields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|name| {
+                let name = name.trim();
+                Field::parse(name)
+                    .map(|field| (name.to_string(), field))
+                    .ok_or_else(|| format!("unknown field {}", name))
+            })
+            .collect::<Result<_, _>>()
+            .map(Fields)
+    }
+}
+
+impl Fields {
+    /// The names as they were given.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// The values of the map in order, with the addresses in hex and the sizes in `units`.
+    pub fn values<'a>(&self, m: &'a MemoryMap, units: Units) -> Vec<Value<'a>> {
+        self.0
+            .iter()
+            .map(|(_, field)| match (field, field.number(m)) {
+                (Field::From | Field::To, Some(address)) => {
+                    Value::Text(Cow::Owned(format!("{:x}", address)))
+                }
+                (Field::Size(_) | Field::Uss, Some(kb)) => Value::Number(units.value(kb)),
+                (_, Some(number)) => Value::Number(number),
+                (_, None) => Value::Text(field.text(m)),
+            })
+            .collect()
+    }
+
+    /// The values of the map to serialize as an object keyed by their names, in order.
+    #[cfg(feature = "serde")]
+    pub fn row<'a>(&'a self, m: &'a MemoryMap, units: Units) -> Row<'a> {
+        Row {
+            names: self,
+            values: self.values(m, units),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub struct Row<'a> {
+    names: &'a Fields,
+    values: Vec<Value<'a>>,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (name, value) in self.names.names().zip(&self.values) {
+            match value {
+                Value::Number(number) => map.serialize_entry(name, number)?,
+                Value::Text(text) => map.serialize_entry(name, text)?,
+            }
+        }
+        map.end()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field { field: Field, hex: bool },
+}
+
+/// A parsed `--template`. Tokens are `{name}` or `{name:x}` for hex, where the name is
+/// `from`, `to`, `perms`, `offset`, `device`, `inode`, `path`, `flags`, `uss` or any size
+/// field in any case, eg. `{rss}`. `{{` and `}}` are literal braces.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed {{ in template: {}", s))?;
+                    let token = &rest[..end];
+                    chars = rest[end + 1..].chars();
+
+                    let (name, hex) = match token.split_once(':') {
+                        Some((name, "x")) => (name, true),
+                        Some((_, spec)) => {
+                            return Err(format!("unknown format :{} in {{{}}}", spec, token))
+                        }
+                        None => (token, false),
+                    };
+                    let field = Field::parse(name)
+                        .ok_or_else(|| format!("unknown template field {{{}}}", name))?;
+                    if hex && !field.is_number() {
+                        return Err(format!("{{{}}} isn't a number so can't be hex", name));
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field { field, hex });
+                }
+                '}' => return Err(format!("unmatched }} in template: {}", s)),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    pub fn render(&self, m: &MemoryMap) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => line.push_str(literal),
+                Part::Field { field, hex } => match field.number(m) {
+                    Some(number) if *hex => write!(line, "{:x}", number).unwrap(),
+                    Some(number) => write!(line, "{}", number).unwrap(),
+                    None => line.push_str(&field.text(m)),
+                },
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let m = "7f3d2c21b000-7f3d2c21e000 r-xp 00000000 fe:00 1234 /usr/lib/libc.so.6
+Size:                 12 kB
+Rss:                   8 kB
+VmFlags: rd ex
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+
+        let template = "{from:x}-{to:x} {rss}kB {Swap}kB {perms} {path} {{literal}}"
+            .parse::<Template>()
+            .unwrap();
+        assert_eq!(
+            "7f3d2c21b000-7f3d2c21e000 8kB 0kB r-xp /usr/lib/libc.so.6 {literal}",
+            template.render(&m)
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        let m = "7f3d2c21b000-7f3d2c21e000 r-xp 00000000 fe:00 1234 /usr/lib/libc.so.6
+Rss:                   8 kB
+VmFlags: rd ex
+"
+        .parse::<MemoryMap>()
+        .unwrap();
+
+        let fields = "from, Rss,swap,path".parse::<Fields>().unwrap();
+        assert_eq!(
+            vec!["from", "Rss", "swap", "path"],
+            fields.names().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                Value::Text(Cow::Borrowed("7f3d2c21b000")),
+                Value::Number(8192),
+                Value::Number(0),
+                Value::Text(Cow::Borrowed("/usr/lib/libc.so.6")),
+            ],
+            fields.values(&m, Units::Bytes)
+        );
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            r#"{"from":"7f3d2c21b000","Rss":8,"swap":0,"path":"/usr/lib/libc.so.6"}"#,
+            serde_json::to_string(&fields.row(&m, Units::Kb)).unwrap()
+        );
+        assert!("rss,nope".parse::<Fields>().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("{nope}".parse::<Template>().is_err());
+        assert!("{path:x}".parse::<Template>().is_err());
+        assert!("{rss:q}".parse::<Template>().is_err());
+        assert!("{rss".parse::<Template>().is_err());
+        assert!("rss}".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! `shmaps top`, every process we can read by how much memory it uses. This reads
+//! smaps_rollup, which the kernel sums up itself, so a pass over every process is cheap
+//! enough to repeat every couple of seconds.
+
+use std::{fmt::Write, io, path::Path};
+
+use crate::{parse, process, units::Units};
+
+/// The memory of one process, all in kB.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Usage {
+    pub pid: usize,
+    pub comm: String,
+    pub rss: usize,
+    pub pss: usize,
+    pub uss: usize,
+    pub swap: usize,
+}
+
+pub fn read_usage(proc_root: &Path, pid: usize) -> io::Result<Usage> {
+    let contents = std::fs::read_to_string(process::proc_path(proc_root, pid, "smaps_rollup"))?;
+    let rollup = parse::parse_rollup(&contents)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Usage {
+        pid,
+        comm: process::comm(proc_root, pid).unwrap_or_default(),
+        rss: rollup.rss().unwrap_or_default(),
+        pss: rollup.pss().unwrap_or_default(),
+        uss: rollup.uss().unwrap_or_default(),
+        swap: rollup.swap().unwrap_or_default(),
+    })
+}
+
+/// The usage of every process that can be read. Kernel threads have an empty
+/// smaps_rollup and most other processes belong to other users, so those are left out.
+pub fn read_all(proc_root: &Path) -> io::Result<Vec<Usage>> {
+    Ok(process::pids(proc_root)?
+        .into_iter()
+        .filter_map(|pid| read_usage(proc_root, pid).ok())
+        .collect())
+}
+
+pub fn format(usages: &[Usage], units: Units) -> String {
+    let mut table = format!(
+        "{:>8} {:<16} {:>14} {:>14} {:>14} {:>14}\n",
+        "PID", "COMMAND", "USS", "PSS", "RSS", "SWAP"
+    );
+    for usage in usages {
+        writeln!(
+            table,
+            "{:>8} {:<16} {:>14} {:>14} {:>14} {:>14}",
+            usage.pid,
+            usage.comm,
+            units.format(usage.uss),
+            units.format(usage.pss),
+            units.format(usage.rss),
+            units.format(usage.swap)
+        )
+        .unwrap();
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn test_read_usage() {
+        let proc_root = Scratch::new("top");
+        let dir = proc_root.join("42");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("comm"), "sleep\n").unwrap();
+        std::fs::write(
+            dir.join("smaps_rollup"),
+            "5581d1a2c000-7ffd7a3f1000 ---p 00000000 00:00 0                          [rollup]
+Rss:                1536 kB
+Pss:                 400 kB
+Shared_Clean:       1200 kB
+Private_Clean:        80 kB
+Private_Dirty:       256 kB
+Swap:                 12 kB
+",
+        )
+        .unwrap();
+
+        let usages = read_all(proc_root.path()).unwrap();
+
+        assert_eq!(
+            vec![Usage {
+                pid: 42,
+                comm: "sleep".to_string(),
+                rss: 1536,
+                pss: 400,
+                uss: 336,
+                swap: 12,
+            }],
+            usages
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    memory_map::{Device, MemoryMap, Numeric, Range, SmapsFields, SIZE_FIELDS},
+    units::Units,
+};
+
+/// Sizes summed across a set of maps, all in kB.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Totals {
+    pub maps: usize,
+    pub size: usize,
+    pub rss: usize,
+    pub pss: usize,
+    pub referenced: usize,
+    /// Private_Clean and Private_Dirty.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub uss: usize,
+    /// Shared_Clean and Shared_Dirty.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shared: usize,
+    pub swap: usize,
+    pub swap_pss: usize,
+    pub anonymous: usize,
+    pub locked: usize,
+}
+
+impl Totals {
+    pub fn add(&mut self, m: &MemoryMap) {
+        self.maps += 1;
+        self.size += m.size().unwrap_or_default();
+        self.rss += m.rss().unwrap_or_default();
+        self.pss += m.pss().unwrap_or_default();
+        self.referenced += m.referenced().unwrap_or_default();
+        self.uss += m.uss().unwrap_or_default();
+        self.shared += m.shared().unwrap_or_default();
+        self.swap += m.swap().unwrap_or_default();
+        self.swap_pss += m.swap_pss().unwrap_or_default();
+        self.anonymous += m.anonymous().unwrap_or_default();
+        self.locked += m.locked().unwrap_or_default();
+    }
+}
+
+impl Totals {
+    pub fn format(&self, units: Units) -> String {
+        [
+            format!("Maps:       {}", self.maps),
+            format!("Size:       {}", units.format(self.size)),
+            format!("Rss:        {}", units.format(self.rss)),
+            format!("Pss:        {}", units.format(self.pss)),
+            format!("Uss:        {}", units.format(self.uss)),
+            format!("Shared:     {}", units.format(self.shared)),
+            format!("Referenced: {}", units.format(self.referenced)),
+            format!("Swap:       {}", units.format(self.swap)),
+            format!("SwapPss:    {}", units.format(self.swap_pss)),
+            format!("Anonymous:  {}", units.format(self.anonymous)),
+            format!("Locked:     {}", units.format(self.locked)),
+        ]
+        .join("\n")
+    }
+}
+
+/// A total that smaps_rollup and the sum of the maps in smaps disagree on.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Discrepancy {
+    pub field: &'static str,
+    pub rollup: usize,
+    pub detailed: usize,
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is {} kB in smaps_rollup but {} kB summed from smaps",
+            self.field, self.rollup, self.detailed
+        )
+    }
+}
+
+/// Compare the totals from smaps_rollup with those summed from smaps. The rollup has no
+/// Size and counts as a single map, so those aren't compared. Each map rounds its Pss
+/// down to a whole kB where the rollup doesn't, so the Pss sums may fall short by up to
+/// a kB per map.
+pub fn compare_totals(rollup: &Totals, detailed: &Totals) -> Vec<Discrepancy> {
+    let rounding = detailed.maps;
+    [
+        ("Rss", rollup.rss, detailed.rss, 0),
+        ("Pss", rollup.pss, detailed.pss, rounding),
+        ("Referenced", rollup.referenced, detailed.referenced, 0),
+        ("Swap", rollup.swap, detailed.swap, 0),
+        ("SwapPss", rollup.swap_pss, detailed.swap_pss, rounding),
+        ("Anonymous", rollup.anonymous, detailed.anonymous, 0),
+        ("Locked", rollup.locked, detailed.locked, 0),
+    ]
+    .into_iter()
+    .filter(|(_, rollup, detailed, tolerance)| detailed > rollup || rollup - detailed > *tolerance)
+    .map(|(field, rollup, detailed, _)| Discrepancy {
+        field,
+        rollup,
+        detailed,
+    })
+    .collect()
+}
+
+/// A run of sizes, biggest first, as shares of `total` along with how much of it they
+/// make up between them so far.
+pub struct Shares {
+    total: usize,
+    so_far: usize,
+}
+
+impl Shares {
+    pub fn new(total: usize) -> Self {
+        Shares { total, so_far: 0 }
+    }
+
+    /// The share of the next size and the share of every size up to it, eg. ` 12.5%  40.0%`.
+    pub fn next(&mut self, size: usize) -> String {
+        self.so_far += size;
+        let percent = |size| match self.total {
+            0 => 0.0,
+            total => size as f64 * 100.0 / total as f64,
+        };
+        format!("{:>5.1}% {:>5.1}%", percent(size), percent(self.so_far))
+    }
+}
+
+pub fn totals<'a>(maps: impl IntoIterator<Item = &'a MemoryMap>) -> Totals {
+    let mut totals = Totals::default();
+    for m in maps {
+        totals.add(m);
+    }
+    totals
+}
+
+/// How many maps each file is split across.
+pub fn segment_counts(maps: &[MemoryMap]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for path in maps.iter().filter_map(|m| m.path.as_ref()) {
+        *counts.entry(path.clone()).or_default() += 1;
+    }
+    counts
+}
+
+/// Totals for each value of `key`.
+pub fn group_by<K: Ord>(maps: &[MemoryMap], key: impl Fn(&MemoryMap) -> K) -> BTreeMap<K, Totals> {
+    let mut groups = BTreeMap::<_, Totals>::new();
+    for m in maps {
+        groups.entry(key(m)).or_default().add(m);
+    }
+    groups
+}
+
+/// The top of the user address space with 5-level paging, the largest of any
+/// architecture Linux runs on.
+const USER_SPACE_END: usize = 1 << 56;
+
+/// Whether the map is in the user half of the address space. `[vsyscall]` is mapped
+/// into every process from the top of the kernel's half, so it isn't.
+pub fn in_user_space(m: &MemoryMap) -> bool {
+    m.address_range.to <= USER_SPACE_END && m.path.as_deref() != Some("[vsyscall]")
+}
+
+/// The unmapped holes between consecutive maps in user space, which must be in
+/// address order.
+pub fn gaps(maps: &[MemoryMap]) -> Vec<Range> {
+    let maps = maps.iter().filter(|m| in_user_space(m)).collect::<Vec<_>>();
+    maps.windows(2)
+        .filter(|pair| pair[0].address_range.to < pair[1].address_range.from)
+        .map(|pair| Range {
+            from: pair[0].address_range.to,
+            to: pair[1].address_range.from,
+        })
+        .collect()
+}
+
+/// One map standing for all of `maps`, for the ones --top leaves out, so they can be printed
+/// the same way as the maps shown. It spans them all and each size is their total, apart
+/// from the page sizes which are the first map's.
+pub fn rest(maps: &[MemoryMap]) -> Option<MemoryMap> {
+    let first = maps.first()?;
+    let mut sizes = SmapsFields::default();
+    for &name in SIZE_FIELDS {
+        let total = match name {
+            "KernelPageSize" | "MMUPageSize" => first.sizes.get(name).map(Numeric::value),
+            _ => maps
+                .iter()
+                .filter_map(|m| m.sizes.get(name).map(Numeric::value))
+                .reduce(|total, size| total + size),
+        };
+        if let Some(total) = total {
+            sizes.insert(name.to_string(), Numeric::Kb(total));
+        }
+    }
+    Some(MemoryMap {
+        address_range: Range {
+            from: maps.iter().map(|m| m.address_range.from).min()?,
+            to: maps.iter().map(|m| m.address_range.to).max()?,
+        },
+        offset: 0,
+        inode: 0,
+        path: Some(format!("{} other maps", maps.len())),
+        deleted: false,
+        sizes,
+        ..first.clone()
+    })
+}
+
+/// Totals for each file backed map by `(device, inode)`, so the same file is counted
+/// together however it was reached. Maps with inode 0 aren't backed by a file.
+pub fn group_by_inode(maps: &[MemoryMap]) -> BTreeMap<(Device, u64), Totals> {
+    let mut groups = BTreeMap::<_, Totals>::new();
+    for m in maps.iter().filter(|m| m.inode != 0) {
+        groups.entry((m.device, m.inode)).or_default().add(m);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gaps() {
+        let maps = [
+            "1000-2000",
+            "2000-3000",
+            "5000-6000",
+            "6000-8000",
+            "a000-b000",
+            "ffffffffff600000-ffffffffff601000",
+        ]
+        .iter()
+        .map(|range| {
+            format!(
+                "{} rw-p 00000000 00:00 0 \nSize: 4 kB\nVmFlags: rd wr\n",
+                range
+            )
+            .parse()
+            .unwrap()
+        })
+        .collect::<Vec<MemoryMap>>();
+
+        assert_eq!(
+            vec![
+                Range {
+                    from: 0x3000,
+                    to: 0x5000
+                },
+                Range {
+                    from: 0x8000,
+                    to: 0xa000
+                }
+            ],
+            gaps(&maps)
+        );
+    }
+
+    #[test]
+    fn test_rest() {
+        let maps = [
+            "3000-4000 rw-p 00000000 00:00 0\nKernelPageSize: 4 kB\nRss: 4 kB\n",
+            "1000-2000 r--p 00001000 fe:00 12 /usr/lib/libc.so.6\nKernelPageSize: 4 kB\nRss: 2 kB\nSwap: 1 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        let rest = rest(&maps).unwrap();
+        assert_eq!("00001000-00004000", rest.address_range.to_string());
+        assert_eq!(Some("2 other maps"), rest.path.as_deref());
+        assert_eq!((Some(6), Some(1)), (rest.rss(), rest.swap()));
+        assert_eq!(Some(&Numeric::Kb(4)), rest.sizes.get("KernelPageSize"));
+        assert_eq!(None, super::rest(&[]));
+    }
+
+    #[test]
+    fn test_totals() {
+        let maps = [
+            "1000-3000 r-xp 00000000 fe:00 12 /usr/lib/libc.so.6\nSize: 8 kB\nRss: 8 kB\nPss: 2 kB\nShared_Clean: 8 kB\n",
+            "3000-4000 rw-p 00000000 00:00 0\nSize: 4 kB\nRss: 4 kB\nPss: 4 kB\nPrivate_Clean: 1 kB\nPrivate_Dirty: 3 kB\nSwap: 8 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        let totals = totals(&maps);
+        assert_eq!((12, 12, 6), (totals.size, totals.rss, totals.pss));
+        assert_eq!((4, 8, 8), (totals.uss, totals.shared, totals.swap));
+        assert!(totals
+            .format(Units::Kb)
+            .contains("Uss:        4 kB\nShared:     8 kB"));
+    }
+
+    #[test]
+    fn test_referenced() {
+        let maps = [
+            "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\nReferenced: 1 kB\n",
+            "2000-3000 rw-p 00000000 00:00 0\nRss: 4 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        assert_eq!(
+            (Some(1), None),
+            (maps[0].referenced(), maps[1].referenced())
+        );
+        let totals = totals(&maps);
+        assert_eq!(1, totals.referenced);
+        assert!(totals.format(Units::Kb).contains("Referenced: 1 kB"));
+    }
+
+    #[test]
+    fn test_anonymous() {
+        // The copy on write pages of a file map are anonymous too.
+        let maps = [
+            "1000-3000 rw-p 00002000 fe:00 12 /usr/lib/libc.so.6\nRss: 8 kB\nAnonymous: 4 kB\n",
+            "3000-4000 rw-p 00000000 00:00 0\nRss: 4 kB\nAnonymous: 4 kB\n",
+        ]
+        .map(|m| m.parse::<MemoryMap>().unwrap());
+        assert_eq!(Some(4), maps[0].anonymous());
+        let totals = totals(&maps);
+        assert_eq!(8, totals.anonymous);
+        assert!(totals.format(Units::Kb).contains("Anonymous:  8 kB"));
+    }
+
+    #[test]
+    fn test_shares() {
+        let mut shares = Shares::new(80);
+        assert_eq!(" 50.0%  50.0%", shares.next(40));
+        assert_eq!(" 25.0%  75.0%", shares.next(20));
+        assert_eq!(" 25.0% 100.0%", shares.next(20));
+        assert_eq!("  0.0%   0.0%", Shares::new(0).next(0));
+    }
+
+    #[test]
+    fn test_compare_totals() {
+        let rollup = Totals {
+            maps: 1,
+            rss: 100,
+            pss: 50,
+            ..Totals::default()
+        };
+        let detailed = Totals {
+            maps: 2,
+            size: 400,
+            rss: 100,
+            pss: 48,
+            ..Totals::default()
+        };
+        assert_eq!(
+            Vec::<Discrepancy>::new(),
+            compare_totals(&rollup, &detailed)
+        );
+
+        let torn = Totals {
+            maps: 2,
+            rss: 104,
+            pss: 47,
+            ..Totals::default()
+        };
+        assert_eq!(
+            vec![
+                Discrepancy {
+                    field: "Rss",
+                    rollup: 100,
+                    detailed: 104
+                },
+                Discrepancy {
+                    field: "Pss",
+                    rollup: 50,
+                    detailed: 47
+                }
+            ],
+            compare_totals(&rollup, &torn)
+        );
+    }
+}
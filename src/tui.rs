@@ -0,0 +1,303 @@
+//! `shmaps tui`, a full screen table of the maps that refreshes itself, with a pane of
+//! every field of the selected map.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    error::{self, ShmapsError},
+    memory_map::MemoryMap,
+    totals,
+    units::Units,
+};
+
+/// The sizes the table can be sorted on, biggest first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum SortColumn {
+    Size,
+    #[default]
+    Rss,
+    Pss,
+    Swap,
+}
+
+impl SortColumn {
+    const ALL: [SortColumn; 4] = [
+        SortColumn::Size,
+        SortColumn::Rss,
+        SortColumn::Pss,
+        SortColumn::Swap,
+    ];
+
+    fn value(&self, m: &MemoryMap) -> usize {
+        match self {
+            SortColumn::Size => m.size(),
+            SortColumn::Rss => m.rss(),
+            SortColumn::Pss => m.pss(),
+            SortColumn::Swap => m.swap(),
+        }
+        .unwrap_or_default()
+    }
+
+    fn next(&self) -> SortColumn {
+        let index = Self::ALL.iter().position(|column| column == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// What's being looked at, kept across refreshes.
+#[derive(Default)]
+struct View {
+    sort: SortColumn,
+    reverse: bool,
+    search: String,
+    searching: bool,
+    table: TableState,
+}
+
+impl View {
+    /// The indexes of the maps with `search` in their path, in the order they're shown.
+    fn visible(&self, memory_map: &[MemoryMap]) -> Vec<usize> {
+        let mut visible = (0..memory_map.len())
+            .filter(|&index| {
+                self.search.is_empty()
+                    || memory_map[index]
+                        .path
+                        .as_deref()
+                        .is_some_and(|path| path.contains(&self.search))
+            })
+            .collect::<Vec<_>>();
+        visible.sort_by_key(|&index| std::cmp::Reverse(self.sort.value(&memory_map[index])));
+        if self.reverse {
+            visible.reverse();
+        }
+        visible
+    }
+
+    /// Returns false to quit.
+    fn handle_key(&mut self, code: KeyCode, shown: usize) -> bool {
+        if self.searching {
+            match code {
+                KeyCode::Enter | KeyCode::Esc => self.searching = false,
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            }
+            self.table.select_first();
+            return true;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('s') => self.sort = self.sort.next(),
+            KeyCode::Char('r') => self.reverse = !self.reverse,
+            KeyCode::Char('/') => {
+                self.searching = true;
+                self.search.clear();
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::PageDown => self.table.scroll_down_by(20),
+            KeyCode::PageUp => self.table.scroll_up_by(20),
+            KeyCode::Home => self.table.select_first(),
+            KeyCode::End => self.table.select(shown.checked_sub(1)),
+            _ => {}
+        }
+        true
+    }
+}
+
+fn draw(frame: &mut Frame, title: &str, memory_map: &[MemoryMap], view: &mut View, units: Units) {
+    let visible = view.visible(memory_map);
+    if view
+        .table
+        .selected()
+        .is_none_or(|selected| selected >= visible.len())
+    {
+        view.table.select((!visible.is_empty()).then_some(0));
+    }
+
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list, detail] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(body);
+
+    let total = totals::totals(memory_map);
+    frame.render_widget(
+        Line::from(format!(
+            "{}  {} maps  Rss {}  Pss {}  Uss {}  Swap {}",
+            title,
+            total.maps,
+            units.format(total.rss),
+            units.format(total.pss),
+            units.format(total.uss),
+            units.format(total.swap)
+        ))
+        .style(Style::new().add_modifier(Modifier::BOLD)),
+        header,
+    );
+
+    let headings = ["ADDRESS", "PERMS", "SIZE", "RSS", "PSS", "SWAP", "PATH"].map(|heading| {
+        let sorted = format!("{:?}", view.sort).to_uppercase();
+        match (heading == sorted, view.reverse) {
+            (true, false) => format!("{} v", heading),
+            (true, true) => format!("{} ^", heading),
+            (false, _) => heading.to_string(),
+        }
+    });
+    let rows = visible.iter().map(|&index| {
+        let m = &memory_map[index];
+        let size = |value: Option<usize>| units.format(value.unwrap_or_default());
+        Row::new([
+            format!("{:x}", m.address_range.from),
+            m.permissions.to_string(),
+            size(m.size()),
+            size(m.rss()),
+            size(m.pss()),
+            size(m.swap()),
+            m.path.clone().unwrap_or_default(),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(12),
+            Constraint::Length(5),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Min(10),
+        ],
+    )
+    .header(Row::new(headings).style(Style::new().add_modifier(Modifier::BOLD)))
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered());
+    frame.render_stateful_widget(table, list, &mut view.table);
+
+    let selected = view
+        .table
+        .selected()
+        .and_then(|selected| visible.get(selected))
+        .map(|&index| &memory_map[index]);
+    frame.render_widget(
+        Paragraph::new(selected.map(|m| m.to_smaps()).unwrap_or_default())
+            .block(Block::bordered().title("Map")),
+        detail,
+    );
+
+    let footer_text = if view.searching {
+        format!("Search paths: {}_", view.search)
+    } else if !view.search.is_empty() {
+        format!(
+            "Paths containing \"{}\"  s sort  r reverse  / search  q quit",
+            view.search
+        )
+    } else {
+        "s sort  r reverse  / search  arrows select  q quit".to_string()
+    };
+    frame.render_widget(Line::from(footer_text), footer);
+}
+
+fn run_loop(
+    terminal: &mut DefaultTerminal,
+    title: &str,
+    interval: Duration,
+    units: Units,
+    mut read: impl FnMut() -> error::Result<Vec<MemoryMap>>,
+) -> error::Result<()> {
+    let terminal_error = |err| ShmapsError::io("draw on", Path::new("the terminal"), err);
+    let mut view = View::default();
+
+    loop {
+        let memory_map = read()?;
+        let refresh_at = Instant::now() + interval;
+
+        loop {
+            terminal
+                .draw(|frame| draw(frame, title, &memory_map, &mut view, units))
+                .map_err(terminal_error)?;
+
+            let timeout = refresh_at.saturating_duration_since(Instant::now());
+            if !event::poll(timeout).map_err(terminal_error)? {
+                break;
+            }
+            if let Event::Key(key) = event::read().map_err(terminal_error)? {
+                if key.kind == KeyEventKind::Press
+                    && !view.handle_key(key.code, view.visible(&memory_map).len())
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Show the maps `read` gives, reading them again every `interval` until q is pressed.
+pub fn run(
+    title: &str,
+    interval: Duration,
+    units: Units,
+    read: impl FnMut() -> error::Result<Vec<MemoryMap>>,
+) -> error::Result<()> {
+    let mut terminal = ratatui::try_init()
+        .map_err(|err| ShmapsError::io("set up", Path::new("the terminal"), err))?;
+    let result = run_loop(&mut terminal, title, interval, units, read);
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(range: &str, path: &str, rss: usize, swap: usize) -> MemoryMap {
+        format!(
+            "{} rw-p 00000000 00:00 0 {}\nRss: {} kB\nSwap: {} kB\nVmFlags: rd wr\n",
+            range, path, rss, swap
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_visible() {
+        let memory_map = [
+            map("1000-2000", "/usr/lib/libc.so.6", 4, 8),
+            map("2000-3000", "[heap]", 12, 0),
+            map("3000-4000", "/usr/lib/libm.so.6", 8, 0),
+        ];
+        let mut view = View::default();
+        assert_eq!(vec![1, 2, 0], view.visible(&memory_map));
+
+        view.sort = view.sort.next().next();
+        assert_eq!(SortColumn::Swap, view.sort);
+        assert_eq!(0, view.visible(&memory_map)[0]);
+
+        view.handle_key(KeyCode::Char('/'), 3);
+        for c in "lib".chars() {
+            view.handle_key(KeyCode::Char(c), 3);
+        }
+        view.handle_key(KeyCode::Enter, 3);
+        view.handle_key(KeyCode::Char('r'), 3);
+        view.sort = SortColumn::Rss;
+        assert_eq!(vec![0, 2], view.visible(&memory_map));
+    }
+}
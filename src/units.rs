@@ -0,0 +1,249 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    memory_map::{MemoryMap, Numeric, SIZE_FIELDS},
+    process,
+};
+
+/// The unit sizes are printed in. Everything is worked out in kB, the unit smaps uses,
+/// and only converted on the way out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Units {
+    /// Whichever of kB, MB and GB reads best for each size
+    Auto,
+    #[default]
+    Kb,
+    Mb,
+    Gb,
+    Bytes,
+    /// Pages of the system's page size
+    Pages,
+}
+
+impl Units {
+    /// A size as a whole number for output meant for machines, such as CSV. MB and GB
+    /// wouldn't be whole, so only bytes and pages change it from kB.
+    pub fn value(&self, kb: usize) -> usize {
+        match self {
+            Units::Auto | Units::Kb | Units::Mb | Units::Gb => kb,
+            Units::Bytes => kb * 1024,
+            Units::Pages => kb * 1024 / process::page_size(),
+        }
+    }
+
+    /// The suffix of the numbers `value` gives.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Units::Auto | Units::Kb | Units::Mb | Units::Gb => "kB",
+            Units::Bytes => "B",
+            Units::Pages => "pages",
+        }
+    }
+
+    /// A reminder to show once that the sizes aren't as precise as the units suggest.
+    pub fn note(&self) -> Option<String> {
+        let (_, kb) = granularities();
+        let kb = kb.join(", ");
+        match self {
+            Units::Bytes => Some(format!(
+                "smaps counts sizes in whole pages ({} to the kB), so the bytes are only accurate to a page",
+                kb
+            )),
+            Units::Pages => Some(format!(
+                "smaps counts {} to the kB, so in pages they're rounded down",
+                kb
+            )),
+            _ => None,
+        }
+    }
+
+    /// The note along with which fields are accurate to a page and which to the kB,
+    /// for output read by other programs.
+    #[cfg(feature = "serde")]
+    pub fn granularity(&self) -> Option<Granularity> {
+        let (page, kb) = granularities();
+        self.note().map(|message| Granularity { message, page, kb })
+    }
+
+    fn format_signed(&self, kb: i64, sign: bool) -> String {
+        const MB: i64 = 1024;
+        const GB: i64 = 1024 * 1024;
+        let scaled = |divisor: i64, decimals: usize, suffix: &str| match sign {
+            true => format!("{:+.*} {}", decimals, kb as f64 / divisor as f64, suffix),
+            false => format!("{:.*} {}", decimals, kb as f64 / divisor as f64, suffix),
+        };
+        let unit = match self {
+            Units::Auto if kb.abs() >= GB => Units::Gb,
+            Units::Auto if kb.abs() >= MB => Units::Mb,
+            Units::Auto => Units::Kb,
+            unit => *unit,
+        };
+        match unit {
+            Units::Mb => scaled(MB, 1, "MB"),
+            Units::Gb => scaled(GB, 2, "GB"),
+            _ => {
+                let value = match unit {
+                    Units::Bytes => kb * 1024,
+                    Units::Pages => kb * 1024 / process::page_size() as i64,
+                    _ => kb,
+                };
+                match sign {
+                    true => format!("{:+} {}", value, unit.suffix()),
+                    false => format!("{} {}", value, unit.suffix()),
+                }
+            }
+        }
+    }
+
+    pub fn format(&self, kb: usize) -> String {
+        self.format_signed(kb as i64, false)
+    }
+
+    /// Like `format` but always with a sign.
+    pub fn format_delta(&self, kb: i64) -> String {
+        self.format_signed(kb, true)
+    }
+
+    /// The map with its sizes in these units, for output that shows the sizes as they're
+    /// stored such as JSON. Only bytes can be stored, anything else stays in kB.
+    pub fn convert<'a>(&self, m: &'a MemoryMap) -> Cow<'a, MemoryMap> {
+        match self {
+            Units::Bytes => {
+                let mut m = m.clone();
+                for value in m.sizes.values_mut() {
+                    if let Some(bytes) = value.as_bytes() {
+                        *value = Numeric::Bytes(bytes as usize);
+                    }
+                }
+                Cow::Owned(m)
+            }
+            _ => Cow::Borrowed(m),
+        }
+    }
+}
+
+/// The size fields accurate to a page, then those only accurate to the kB.
+fn granularities() -> (Vec<&'static str>, Vec<&'static str>) {
+    SIZE_FIELDS
+        .iter()
+        .partition(|name| Numeric::Kb(0).is_page_granular(name))
+}
+
+/// `Units::note` as JSON, eg. `{"note":"granularity","message":...,"page":["Size",...],
+/// "kb":["Pss",...]}`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Serialize)]
+#[serde(tag = "note", rename = "granularity")]
+pub struct Granularity {
+    pub message: String,
+    /// The size fields only accurate to a page.
+    pub page: Vec<&'static str>,
+    /// The size fields accurate to the kB.
+    pub kb: Vec<&'static str>,
+}
+
+/// A size given on the command line in kB, eg. `512`, or with a unit, eg. `4k`, `1M`,
+/// `2GiB` or `10kB`. Units are powers of 1024 as in smaps.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("expected a size such as 512, 4k or 1M, not {}", value))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let (unit, bytes) = match unit.strip_suffix("ib").or_else(|| unit.strip_suffix('b')) {
+        Some(unit) => (unit, true),
+        None => (unit.as_str(), false),
+    };
+    let kb = match unit {
+        "" if bytes => return Ok((number / 1024.0).ceil() as usize),
+        "" | "k" => 1.0,
+        "m" => 1024.0,
+        "g" => 1024.0 * 1024.0,
+        "t" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("unknown unit {}, expected k, M, G or T", unit)),
+    };
+    Ok((number * kb) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!("1536 kB", Units::Kb.format(1536));
+        assert_eq!("1.5 MB", Units::Mb.format(1536));
+        assert_eq!("0.50 GB", Units::Gb.format(512 * 1024));
+        assert_eq!("512 kB", Units::Auto.format(512));
+        assert_eq!("1.5 MB", Units::Auto.format(1536));
+        assert_eq!("2.00 GB", Units::Auto.format(2 * 1024 * 1024));
+        assert_eq!("+2048 B", Units::Bytes.format_delta(2));
+        assert_eq!("-1.5 MB", Units::Auto.format_delta(-1536));
+        assert_eq!("+0 kB", Units::Auto.format_delta(0));
+    }
+
+    #[test]
+    fn test_convert() {
+        let m = "1000-2000 rw-p 00000000 00:00 0\nRss: 4 kB\nProtectionKey: 3\n"
+            .parse::<MemoryMap>()
+            .unwrap();
+        let bytes = Units::Bytes.convert(&m);
+        assert_eq!(Some(&Numeric::Bytes(4096)), bytes.sizes.get("Rss"));
+        assert_eq!(Some(4096), bytes.sizes.get("Rss").unwrap().as_bytes());
+        // A number isn't a size to convert.
+        assert_eq!(m.flags, bytes.flags);
+        assert_eq!(None, Numeric::Number(3).as_bytes());
+        assert!(matches!(Units::Mb.convert(&m), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_note() {
+        assert_eq!(None, Units::Auto.note());
+        let note = Units::Pages.note().unwrap();
+        assert!(note.contains("Pss_Anon") && note.contains("SwapPss"));
+        assert!(!note.contains("Rss,"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_granularity() {
+        assert!(Units::Kb.granularity().is_none());
+        let granularity = Units::Bytes.granularity().unwrap();
+        assert!(granularity.page.contains(&"Rss"));
+        assert_eq!(
+            vec![
+                "Pss",
+                "Pss_Dirty",
+                "Pss_Anon",
+                "Pss_File",
+                "Pss_Shmem",
+                "SwapPss"
+            ],
+            granularity.kb
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(512), parse_size("512"));
+        assert_eq!(Ok(4), parse_size("4k"));
+        assert_eq!(Ok(4), parse_size("4kB"));
+        assert_eq!(Ok(1024), parse_size("1M"));
+        assert_eq!(Ok(1536), parse_size("1.5MiB"));
+        assert_eq!(Ok(2 * 1024 * 1024), parse_size("2G"));
+        assert_eq!(Ok(4), parse_size("4096B"));
+        assert_eq!(Ok(4), parse_size("4096b"));
+        assert_eq!(Ok(4), parse_size("4kb"));
+        assert_eq!(Ok(4), parse_size("4KB"));
+        assert_eq!(Ok(1024), parse_size("1mib"));
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+}